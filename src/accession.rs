@@ -0,0 +1,124 @@
+pub const SCAN_START_TIME: &str = "MS:1000016";
+pub const INSTRUMENT_MODEL: &str = "MS:1000031";
+/// Free-text description of changes made to an instrument
+pub const CUSTOMIZATION: &str = "MS:1000032";
+/// m/z, used as a unit
+pub const MZ: &str = "MS:1000040";
+pub const CHARGE_STATE: &str = "MS:1000041";
+pub const PEAK_INTENSITY: &str = "MS:1000042";
+pub const COLLISION_ENERGY: &str = "MS:1000045";
+pub const SCIEX_INSTRUMENT_MODEL: &str = "MS:1000121";
+pub const BRUKER_DALTONICS_INSTRUMENT_MODEL: &str = "MS:1000122";
+pub const SHIMADZU_INSTRUMENT_MODEL: &str = "MS:1000124";
+pub const THERMO_FINNIGAN_INSTRUMENT_MODEL: &str = "MS:1000125";
+pub const WATERS_INSTRUMENT_MODEL: &str = "MS:1000126";
+pub const CENTROID_SPECTRUM: &str = "MS:1000127";
+pub const PROFILE_SPECTRUM: &str = "MS:1000128";
+pub const NEGATIVE_SCAN: &str = "MS:1000129";
+pub const POSITIVE_SCAN: &str = "MS:1000130";
+pub const NUMBER_OF_DETECTOR_COUNTS: &str = "MS:1000131";
+/// collision-induced dissociation
+pub const CID: &str = "MS:1000133";
+pub const PLASMA_DESORPTION: &str = "MS:1000134";
+pub const POST_SOURCE_DECAY: &str = "MS:1000135";
+pub const SURFACE_INDUCED_DISSOCIATION: &str = "MS:1000136";
+pub const TOTAL_ION_CURRENT_CHROMATOGRAM: &str = "MS:1000235";
+/// blackbody infrared radiative dissociation
+pub const BIRD: &str = "MS:1000242";
+/// electron capture dissociation
+pub const ECD: &str = "MS:1000250";
+/// infrared multiphoton dissociation
+pub const IRMPD: &str = "MS:1000262";
+/// sustained off-resonance irradiation
+pub const SORI: &str = "MS:1000282";
+pub const TOTAL_ION_CURRENT: &str = "MS:1000285";
+/// beam-type collision-induced dissociation
+pub const HCD: &str = "MS:1000422";
+pub const PHOTODISSOCIATION: &str = "MS:1000435";
+pub const LTQ: &str = "MS:1000447";
+pub const LTQ_FT: &str = "MS:1000448";
+pub const LTQ_ORBITRAP: &str = "MS:1000449";
+pub const THERMO_FISHER_SCIENTIFIC_INSTRUMENT_MODEL: &str = "MS:1000483";
+pub const AGILENT_INSTRUMENT_MODEL: &str = "MS:1000490";
+pub const THERMO_SCIENTIFIC_INSTRUMENT_MODEL: &str = "MS:1000494";
+pub const SCAN_WINDOW_UPPER_LIMIT: &str = "MS:1000500";
+pub const SCAN_WINDOW_LOWER_LIMIT: &str = "MS:1000501";
+pub const BASE_PEAK_MZ: &str = "MS:1000504";
+pub const BASE_PEAK_INTENSITY: &str = "MS:1000505";
+pub const MS_LEVEL: &str = "MS:1000511";
+pub const FILTER_STRING: &str = "MS:1000512";
+pub const MZ_ARRAY: &str = "MS:1000514";
+pub const INTENSITY_ARRAY: &str = "MS:1000515";
+pub const CHARGE_ARRAY: &str = "MS:1000516";
+pub const INTEGER_32_BIT: &str = "MS:1000519";
+pub const FLOAT_32_BIT: &str = "MS:1000521";
+pub const INTEGER_64_BIT: &str = "MS:1000522";
+pub const FLOAT_64_BIT: &str = "MS:1000523";
+pub const SPECTRUM_REPRESENTATION: &str = "MS:1000525";
+pub const INSTRUMENT_SERIAL_NUMBER: &str = "MS:1000529";
+pub const CONVERSION_TO_MZML: &str = "MS:1000544";
+pub const LTQ_ORBITRAP_XL: &str = "MS:1000556";
+pub const LTQ_FT_ULTRA: &str = "MS:1000557";
+pub const SHA1: &str = "MS:1000569";
+pub const ZLIB_COMPRESSION: &str = "MS:1000574";
+pub const NO_COMPRESSION: &str = "MS:1000576";
+pub const MS1_SPECTRUM: &str = "MS:1000579";
+pub const MSN_SPECTRUM: &str = "MS:1000580";
+pub const TIME_ARRAY: &str = "MS:1000595";
+/// electron transfer dissociation
+pub const ETD: &str = "MS:1000598";
+/// pulsed q dissociation
+pub const PQD: &str = "MS:1000599";
+pub const SELECTED_ION_MZ: &str = "MS:1000744";
+pub const NO_COMBINATION: &str = "MS:1000795";
+pub const CUSTOM_UNRELEASED_SOFTWARE_TOOL: &str = "MS:1000799";
+pub const ISOLATION_WINDOW_TARGET_MZ: &str = "MS:1000827";
+pub const ISOLATION_WINDOW_LOWER_OFFSET: &str = "MS:1000828";
+pub const ISOLATION_WINDOW_UPPER_OFFSET: &str = "MS:1000829";
+pub const QTRAP_5500: &str = "MS:1000931";
+pub const TRIPLETOF_5600: &str = "MS:1000932";
+pub const LTQ_ORBITRAP_VELOS: &str = "MS:1001742";
+pub const Q_EXACTIVE: &str = "MS:1001911";
+/// MS-Numpress linear prediction compression
+pub const NUMPRESS_LINEAR: &str = "MS:1002312";
+/// MS-Numpress positive integer compression
+pub const NUMPRESS_PIC: &str = "MS:1002313";
+/// MS-Numpress short logged float compression
+pub const NUMPRESS_SLOF: &str = "MS:1002314";
+pub const ORBITRAP_FUSION: &str = "MS:1002416";
+/// mean ion mobility drift time array
+pub const MEAN_DRIFT_TIME_ARRAY: &str = "MS:1002477";
+pub const Q_EXACTIVE_HF: &str = "MS:1002523";
+pub const Q_EXACTIVE_PLUS: &str = "MS:1002634";
+pub const ORBITRAP_FUSION_LUMOS: &str = "MS:1002732";
+/// MS-Numpress linear prediction compression followed by zlib compression
+pub const NUMPRESS_LINEAR_ZLIB: &str = "MS:1002746";
+/// MS-Numpress positive integer compression followed by zlib compression
+pub const NUMPRESS_PIC_ZLIB: &str = "MS:1002747";
+/// MS-Numpress short logged float compression followed by zlib compression
+pub const NUMPRESS_SLOF_ZLIB: &str = "MS:1002748";
+pub const MEAN_ION_MOBILITY_ARRAY: &str = "MS:1002816";
+pub const EXTERNAL_HDF5_DATASET: &str = "MS:1002841";
+pub const EXTERNAL_OFFSET: &str = "MS:1002842";
+pub const EXTERNAL_ARRAY_LENGTH: &str = "MS:1002843";
+pub const ION_MOBILITY_ARRAY: &str = "MS:1002893";
+pub const TIMSTOF_PRO: &str = "MS:1003005";
+pub const MEAN_INVERSE_REDUCED_ION_MOBILITY_ARRAY: &str = "MS:1003006";
+pub const RAW_ION_MOBILITY_ARRAY: &str = "MS:1003007";
+pub const RAW_INVERSE_REDUCED_ION_MOBILITY_ARRAY: &str = "MS:1003008";
+pub const ORBITRAP_EXPLORIS_480: &str = "MS:1003028";
+/// raw ion mobility drift time array
+pub const RAW_DRIFT_TIME_ARRAY: &str = "MS:1003153";
+pub const DECONVOLUTED_ION_MOBILITY_ARRAY: &str = "MS:1003154";
+pub const DECONVOLUTED_INVERSE_REDUCED_ION_MOBILITY_ARRAY: &str = "MS:1003155";
+/// deconvoluted ion mobility drift time array
+pub const DECONVOLUTED_DRIFT_TIME_ARRAY: &str = "MS:1003156";
+pub const SECOND: &str = "UO:0000010";
+pub const MILLISECOND: &str = "UO:0000028";
+pub const MICROSECOND: &str = "UO:0000029";
+pub const MINUTE: &str = "UO:0000031";
+pub const HOUR: &str = "UO:0000032";
+pub const VOLT: &str = "UO:0000218";
+pub const MILLIVOLT: &str = "UO:0000247";
+pub const KILOVOLT: &str = "UO:0000248";
+pub const ELECTRONVOLT: &str = "UO:0000266";
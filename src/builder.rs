@@ -0,0 +1,249 @@
+use crate::accession;
+use crate::models::{
+    BinaryDataArray, Chromatogram, CvParam, FileDescription, Run, Scan, ScanList, Spectrum,
+    SpectrumCache,
+};
+use crate::peakpick::ms_param;
+
+impl Spectrum {
+    /// Starts building a spectrum with no params, scans or arrays
+    pub fn builder() -> SpectrumBuilder {
+        SpectrumBuilder::default()
+    }
+}
+
+impl Run {
+    /// Starts building an empty run
+    pub fn builder() -> RunBuilder {
+        RunBuilder::default()
+    }
+}
+
+/// Builder for Spectrum that writes the cvParams and arrays the accessors read back
+#[derive(Debug, Clone)]
+pub struct SpectrumBuilder {
+    spectrum: Spectrum,
+}
+
+impl Default for SpectrumBuilder {
+    fn default() -> Self {
+        SpectrumBuilder {
+            spectrum: Spectrum {
+                id: String::new(),
+                index: 0,
+                default_array_length: 0,
+                source_file_ref: None,
+                data_processing_ref: None,
+                cv_params: Vec::new(),
+                user_params: Vec::new(),
+                scan_list: None,
+                precursors: Vec::new(),
+                binary_data_arrays: Vec::new(),
+                cache: SpectrumCache::default(),
+            },
+        }
+    }
+}
+
+impl SpectrumBuilder {
+    /// Sets the native id
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.spectrum.id = id.into();
+        self
+    }
+
+    /// Sets the index attribute
+    pub fn index(mut self, index: usize) -> Self {
+        self.spectrum.index = index;
+        self
+    }
+
+    /// Sets the ms level (MS:1000511) and the matching MS1 or MSn spectrum term
+    pub fn ms_level(mut self, level: u8) -> Self {
+        let (spectrum_type, name) = match level {
+            1 => (accession::MS1_SPECTRUM, "MS1 spectrum"),
+            _ => (accession::MSN_SPECTRUM, "MSn spectrum"),
+        };
+        let mut ms_level = ms_param(accession::MS_LEVEL, "ms level");
+        ms_level.value = Some(level.to_string());
+        self.set_cv_param(ms_level);
+        self.spectrum.cv_params.retain(|p| {
+            &*p.accession != accession::MS1_SPECTRUM && &*p.accession != accession::MSN_SPECTRUM
+        });
+        self.spectrum.cv_params.push(ms_param(spectrum_type, name));
+        self
+    }
+
+    /// Sets the scan start time (MS:1000016) of the spectrum's scan, in seconds
+    pub fn retention_time(mut self, seconds: f64) -> Self {
+        let mut param = ms_param(accession::SCAN_START_TIME, "scan start time");
+        param.value = Some(seconds.to_string());
+        param.unit_cv_ref = Some("UO".into());
+        param.unit_accession = Some(accession::SECOND.into());
+        param.unit_name = Some("second".into());
+
+        let scan_list = self.spectrum.scan_list.get_or_insert_with(|| ScanList {
+            count: 1,
+            cv_params: vec![ms_param(accession::NO_COMBINATION, "no combination")],
+            scans: Vec::new(),
+        });
+        if scan_list.scans.is_empty() {
+            scan_list.scans.push(Scan {
+                instrument_configuration_ref: None,
+                cv_params: Vec::new(),
+                user_params: Vec::new(),
+                scan_windows: Vec::new(),
+            });
+        }
+        let scan = &mut scan_list.scans[0];
+        scan.cv_params
+            .retain(|p| &*p.accession != accession::SCAN_START_TIME);
+        scan.cv_params.push(param);
+        self
+    }
+
+    /// Sets the decoded m/z and intensity arrays and the defaultArrayLength
+    pub fn peaks(mut self, mz: Vec<f64>, intensity: Vec<f64>) -> Self {
+        self.spectrum.default_array_length = mz.len();
+        self.spectrum.binary_data_arrays = vec![
+            decoded_array(accession::MZ_ARRAY, "m/z array", mz),
+            decoded_array(accession::INTENSITY_ARRAY, "intensity array", intensity),
+        ];
+        self
+    }
+
+    /// Adds a cvParam to the spectrum, replacing any with the same accession
+    pub fn cv_param(mut self, param: CvParam) -> Self {
+        self.set_cv_param(param);
+        self
+    }
+
+    /// Returns the built spectrum
+    pub fn build(self) -> Spectrum {
+        self.spectrum
+    }
+
+    /// Replaces the cvParam with the same accession, or appends the param
+    fn set_cv_param(&mut self, param: CvParam) {
+        let params = &mut self.spectrum.cv_params;
+        match params.iter_mut().find(|p| p.accession == param.accession) {
+            Some(existing) => *existing = param,
+            None => params.push(param),
+        }
+    }
+}
+
+/// Builder for Run
+#[derive(Debug, Clone)]
+pub struct RunBuilder {
+    run: Run,
+}
+
+impl Default for RunBuilder {
+    fn default() -> Self {
+        RunBuilder {
+            run: Run {
+                id: String::new(),
+                start_time: String::new(),
+                sample_ref: None,
+                file_description: None,
+                spectra: Vec::new(),
+                chromatograms: Vec::new(),
+            },
+        }
+    }
+}
+
+impl RunBuilder {
+    /// Sets the run id
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.run.id = id.into();
+        self
+    }
+
+    /// Sets the startTimeStamp, e.g. "2024-01-01T00:00:00Z"
+    pub fn start_time(mut self, start_time: impl Into<String>) -> Self {
+        self.run.start_time = start_time.into();
+        self
+    }
+
+    /// Sets the fileDescription
+    pub fn file_description(mut self, file_description: FileDescription) -> Self {
+        self.run.file_description = Some(file_description);
+        self
+    }
+
+    /// Appends a spectrum
+    pub fn spectrum(mut self, spectrum: Spectrum) -> Self {
+        self.run.spectra.push(spectrum);
+        self
+    }
+
+    /// Appends several spectra
+    pub fn spectra(mut self, spectra: impl IntoIterator<Item = Spectrum>) -> Self {
+        self.run.spectra.extend(spectra);
+        self
+    }
+
+    /// Appends a chromatogram
+    pub fn chromatogram(mut self, chromatogram: Chromatogram) -> Self {
+        self.run.chromatograms.push(chromatogram);
+        self
+    }
+
+    /// Returns the built run
+    pub fn build(self) -> Run {
+        self.run
+    }
+}
+
+/// Creates an uncompressed 64-bit float array already holding its decoded values
+fn decoded_array(array_accession: &str, name: &str, values: Vec<f64>) -> BinaryDataArray {
+    BinaryDataArray {
+        encoded_length: 0,
+        cv_params: vec![
+            ms_param(array_accession, name),
+            ms_param(accession::FLOAT_64_BIT, "64-bit float"),
+            ms_param(accession::NO_COMPRESSION, "no compression"),
+        ],
+        encoded_data: None,
+        decoded_data: Some(values),
+        raw_binary: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{Run, Spectrum};
+    use crate::writer::write_mzml;
+
+    #[test]
+    fn built_spectrum_writes_to_mzml() {
+        let run = Run::builder()
+            .id("synthetic")
+            .spectrum(
+                Spectrum::builder()
+                    .id("scan=1")
+                    .index(0)
+                    .ms_level(2)
+                    .retention_time(42.5)
+                    .peaks(vec![100.0, 200.5], vec![10.0, 20.0])
+                    .build(),
+            )
+            .build();
+
+        let mut out = Vec::new();
+        write_mzml(&run, &mut out).unwrap();
+        let written = String::from_utf8(out).unwrap();
+        assert!(written.contains(r#"<run id="synthetic""#));
+
+        let back = crate::parse_mzml(&written).unwrap();
+        let spectrum = &back.spectra[0];
+        assert_eq!(spectrum.id, "scan=1");
+        assert_eq!(spectrum.default_array_length, 2);
+        assert_eq!(spectrum.ms_level(), Some(2));
+        assert_eq!(spectrum.retention_time(), Some(42.5));
+        assert_eq!(spectrum.mz_array(), Some(&[100.0, 200.5][..]));
+        assert_eq!(spectrum.intensity_array(), Some(&[10.0, 20.0][..]));
+    }
+}
@@ -0,0 +1,352 @@
+use crate::accession;
+
+/// Accession -> name for the PSI-MS and UO terms libms reads or writes, sorted by accession
+const TERMS: [(&str, &str); 105] = [
+    (accession::SCAN_START_TIME, "scan start time"),
+    (accession::INSTRUMENT_MODEL, "instrument model"),
+    (accession::CUSTOMIZATION, "customization"),
+    (accession::MZ, "m/z"),
+    (accession::CHARGE_STATE, "charge state"),
+    (accession::PEAK_INTENSITY, "peak intensity"),
+    (accession::COLLISION_ENERGY, "collision energy"),
+    (accession::SCIEX_INSTRUMENT_MODEL, "SCIEX instrument model"),
+    (
+        accession::BRUKER_DALTONICS_INSTRUMENT_MODEL,
+        "Bruker Daltonics instrument model",
+    ),
+    (
+        accession::SHIMADZU_INSTRUMENT_MODEL,
+        "Shimadzu instrument model",
+    ),
+    (
+        accession::THERMO_FINNIGAN_INSTRUMENT_MODEL,
+        "Thermo Finnigan instrument model",
+    ),
+    (
+        accession::WATERS_INSTRUMENT_MODEL,
+        "Waters instrument model",
+    ),
+    (accession::CENTROID_SPECTRUM, "centroid spectrum"),
+    (accession::PROFILE_SPECTRUM, "profile spectrum"),
+    (accession::NEGATIVE_SCAN, "negative scan"),
+    (accession::POSITIVE_SCAN, "positive scan"),
+    (
+        accession::NUMBER_OF_DETECTOR_COUNTS,
+        "number of detector counts",
+    ),
+    (accession::CID, "collision-induced dissociation"),
+    (accession::PLASMA_DESORPTION, "plasma desorption"),
+    (accession::POST_SOURCE_DECAY, "post-source decay"),
+    (
+        accession::SURFACE_INDUCED_DISSOCIATION,
+        "surface-induced dissociation",
+    ),
+    (
+        accession::TOTAL_ION_CURRENT_CHROMATOGRAM,
+        "total ion current chromatogram",
+    ),
+    (accession::BIRD, "blackbody infrared radiative dissociation"),
+    (accession::ECD, "electron capture dissociation"),
+    (accession::IRMPD, "infrared multiphoton dissociation"),
+    (accession::SORI, "sustained off-resonance irradiation"),
+    (accession::TOTAL_ION_CURRENT, "total ion current"),
+    (accession::HCD, "beam-type collision-induced dissociation"),
+    (accession::PHOTODISSOCIATION, "photodissociation"),
+    (accession::LTQ, "LTQ"),
+    (accession::LTQ_FT, "LTQ FT"),
+    (accession::LTQ_ORBITRAP, "LTQ Orbitrap"),
+    (
+        accession::THERMO_FISHER_SCIENTIFIC_INSTRUMENT_MODEL,
+        "Thermo Fisher Scientific instrument model",
+    ),
+    (
+        accession::AGILENT_INSTRUMENT_MODEL,
+        "Agilent instrument model",
+    ),
+    (
+        accession::THERMO_SCIENTIFIC_INSTRUMENT_MODEL,
+        "Thermo Scientific instrument model",
+    ),
+    (
+        accession::SCAN_WINDOW_UPPER_LIMIT,
+        "scan window upper limit",
+    ),
+    (
+        accession::SCAN_WINDOW_LOWER_LIMIT,
+        "scan window lower limit",
+    ),
+    (accession::BASE_PEAK_MZ, "base peak m/z"),
+    (accession::BASE_PEAK_INTENSITY, "base peak intensity"),
+    (accession::MS_LEVEL, "ms level"),
+    (accession::FILTER_STRING, "filter string"),
+    (accession::MZ_ARRAY, "m/z array"),
+    (accession::INTENSITY_ARRAY, "intensity array"),
+    (accession::CHARGE_ARRAY, "charge array"),
+    (accession::INTEGER_32_BIT, "32-bit integer"),
+    (accession::FLOAT_32_BIT, "32-bit float"),
+    (accession::INTEGER_64_BIT, "64-bit integer"),
+    (accession::FLOAT_64_BIT, "64-bit float"),
+    (
+        accession::SPECTRUM_REPRESENTATION,
+        "spectrum representation",
+    ),
+    (
+        accession::INSTRUMENT_SERIAL_NUMBER,
+        "instrument serial number",
+    ),
+    (accession::CONVERSION_TO_MZML, "Conversion to mzML"),
+    (accession::LTQ_ORBITRAP_XL, "LTQ Orbitrap XL"),
+    (accession::LTQ_FT_ULTRA, "LTQ FT Ultra"),
+    (accession::SHA1, "SHA-1"),
+    (accession::ZLIB_COMPRESSION, "zlib compression"),
+    (accession::NO_COMPRESSION, "no compression"),
+    (accession::MS1_SPECTRUM, "MS1 spectrum"),
+    (accession::MSN_SPECTRUM, "MSn spectrum"),
+    (accession::TIME_ARRAY, "time array"),
+    (accession::ETD, "electron transfer dissociation"),
+    (accession::PQD, "pulsed q dissociation"),
+    (accession::SELECTED_ION_MZ, "selected ion m/z"),
+    (accession::NO_COMBINATION, "no combination"),
+    (
+        accession::CUSTOM_UNRELEASED_SOFTWARE_TOOL,
+        "custom unreleased software tool",
+    ),
+    (
+        accession::ISOLATION_WINDOW_TARGET_MZ,
+        "isolation window target m/z",
+    ),
+    (
+        accession::ISOLATION_WINDOW_LOWER_OFFSET,
+        "isolation window lower offset",
+    ),
+    (
+        accession::ISOLATION_WINDOW_UPPER_OFFSET,
+        "isolation window upper offset",
+    ),
+    (accession::QTRAP_5500, "QTRAP 5500"),
+    (accession::TRIPLETOF_5600, "TripleTOF 5600"),
+    (accession::LTQ_ORBITRAP_VELOS, "LTQ Orbitrap Velos"),
+    (accession::Q_EXACTIVE, "Q Exactive"),
+    (
+        accession::NUMPRESS_LINEAR,
+        "MS-Numpress linear prediction compression",
+    ),
+    (
+        accession::NUMPRESS_PIC,
+        "MS-Numpress positive integer compression",
+    ),
+    (
+        accession::NUMPRESS_SLOF,
+        "MS-Numpress short logged float compression",
+    ),
+    (accession::ORBITRAP_FUSION, "Orbitrap Fusion"),
+    (
+        accession::MEAN_DRIFT_TIME_ARRAY,
+        "mean ion mobility drift time array",
+    ),
+    (accession::Q_EXACTIVE_HF, "Q Exactive HF"),
+    (accession::Q_EXACTIVE_PLUS, "Q Exactive Plus"),
+    (accession::ORBITRAP_FUSION_LUMOS, "Orbitrap Fusion Lumos"),
+    (
+        accession::NUMPRESS_LINEAR_ZLIB,
+        "MS-Numpress linear prediction compression followed by zlib compression",
+    ),
+    (
+        accession::NUMPRESS_PIC_ZLIB,
+        "MS-Numpress positive integer compression followed by zlib compression",
+    ),
+    (
+        accession::NUMPRESS_SLOF_ZLIB,
+        "MS-Numpress short logged float compression followed by zlib compression",
+    ),
+    (
+        accession::MEAN_ION_MOBILITY_ARRAY,
+        "mean ion mobility array",
+    ),
+    (accession::EXTERNAL_HDF5_DATASET, "external HDF5 dataset"),
+    (accession::EXTERNAL_OFFSET, "external offset"),
+    (accession::EXTERNAL_ARRAY_LENGTH, "external array length"),
+    (accession::ION_MOBILITY_ARRAY, "ion mobility array"),
+    (accession::TIMSTOF_PRO, "timsTOF Pro"),
+    (
+        accession::MEAN_INVERSE_REDUCED_ION_MOBILITY_ARRAY,
+        "mean inverse reduced ion mobility array",
+    ),
+    (accession::RAW_ION_MOBILITY_ARRAY, "raw ion mobility array"),
+    (
+        accession::RAW_INVERSE_REDUCED_ION_MOBILITY_ARRAY,
+        "raw inverse reduced ion mobility array",
+    ),
+    (accession::ORBITRAP_EXPLORIS_480, "Orbitrap Exploris 480"),
+    (
+        accession::RAW_DRIFT_TIME_ARRAY,
+        "raw ion mobility drift time array",
+    ),
+    (
+        accession::DECONVOLUTED_ION_MOBILITY_ARRAY,
+        "deconvoluted ion mobility array",
+    ),
+    (
+        accession::DECONVOLUTED_INVERSE_REDUCED_ION_MOBILITY_ARRAY,
+        "deconvoluted inverse reduced ion mobility array",
+    ),
+    (
+        accession::DECONVOLUTED_DRIFT_TIME_ARRAY,
+        "deconvoluted ion mobility drift time array",
+    ),
+    (accession::SECOND, "second"),
+    (accession::MILLISECOND, "millisecond"),
+    (accession::MICROSECOND, "microsecond"),
+    (accession::MINUTE, "minute"),
+    (accession::HOUR, "hour"),
+    (accession::VOLT, "volt"),
+    (accession::MILLIVOLT, "millivolt"),
+    (accession::KILOVOLT, "kilovolt"),
+    (accession::ELECTRONVOLT, "electronvolt"),
+];
+
+/// Term -> parent for the instrument model branch under MS:1000031, sorted by accession
+///
+/// Vendor terms such as "Thermo Scientific instrument model" group the concrete models.
+const INSTRUMENT_MODEL_PARENTS: [(&str, &str); 23] = [
+    (
+        accession::SCIEX_INSTRUMENT_MODEL,
+        accession::INSTRUMENT_MODEL,
+    ),
+    (
+        accession::BRUKER_DALTONICS_INSTRUMENT_MODEL,
+        accession::INSTRUMENT_MODEL,
+    ),
+    (
+        accession::SHIMADZU_INSTRUMENT_MODEL,
+        accession::INSTRUMENT_MODEL,
+    ),
+    (
+        accession::THERMO_FINNIGAN_INSTRUMENT_MODEL,
+        accession::THERMO_FISHER_SCIENTIFIC_INSTRUMENT_MODEL,
+    ),
+    (
+        accession::WATERS_INSTRUMENT_MODEL,
+        accession::INSTRUMENT_MODEL,
+    ),
+    (accession::LTQ, accession::THERMO_FINNIGAN_INSTRUMENT_MODEL),
+    (
+        accession::LTQ_FT,
+        accession::THERMO_FINNIGAN_INSTRUMENT_MODEL,
+    ),
+    (
+        accession::LTQ_ORBITRAP,
+        accession::THERMO_FINNIGAN_INSTRUMENT_MODEL,
+    ),
+    (
+        accession::THERMO_FISHER_SCIENTIFIC_INSTRUMENT_MODEL,
+        accession::INSTRUMENT_MODEL,
+    ),
+    (
+        accession::AGILENT_INSTRUMENT_MODEL,
+        accession::INSTRUMENT_MODEL,
+    ),
+    (
+        accession::THERMO_SCIENTIFIC_INSTRUMENT_MODEL,
+        accession::THERMO_FISHER_SCIENTIFIC_INSTRUMENT_MODEL,
+    ),
+    (
+        accession::LTQ_ORBITRAP_XL,
+        accession::THERMO_SCIENTIFIC_INSTRUMENT_MODEL,
+    ),
+    (
+        accession::LTQ_FT_ULTRA,
+        accession::THERMO_SCIENTIFIC_INSTRUMENT_MODEL,
+    ),
+    (accession::QTRAP_5500, accession::SCIEX_INSTRUMENT_MODEL),
+    (accession::TRIPLETOF_5600, accession::SCIEX_INSTRUMENT_MODEL),
+    (
+        accession::LTQ_ORBITRAP_VELOS,
+        accession::THERMO_SCIENTIFIC_INSTRUMENT_MODEL,
+    ),
+    (
+        accession::Q_EXACTIVE,
+        accession::THERMO_SCIENTIFIC_INSTRUMENT_MODEL,
+    ),
+    (
+        accession::ORBITRAP_FUSION,
+        accession::THERMO_SCIENTIFIC_INSTRUMENT_MODEL,
+    ),
+    (
+        accession::Q_EXACTIVE_HF,
+        accession::THERMO_SCIENTIFIC_INSTRUMENT_MODEL,
+    ),
+    (
+        accession::Q_EXACTIVE_PLUS,
+        accession::THERMO_SCIENTIFIC_INSTRUMENT_MODEL,
+    ),
+    (
+        accession::ORBITRAP_FUSION_LUMOS,
+        accession::THERMO_SCIENTIFIC_INSTRUMENT_MODEL,
+    ),
+    (
+        accession::TIMSTOF_PRO,
+        accession::BRUKER_DALTONICS_INSTRUMENT_MODEL,
+    ),
+    (
+        accession::ORBITRAP_EXPLORIS_480,
+        accession::THERMO_SCIENTIFIC_INSTRUMENT_MODEL,
+    ),
+];
+
+/// Returns the canonical name of a known PSI-MS or UO accession
+pub fn lookup(accession: &str) -> Option<&'static str> {
+    TERMS
+        .binary_search_by(|(term, _)| (*term).cmp(accession))
+        .ok()
+        .map(|i| TERMS[i].1)
+}
+
+/// Returns whether an accession is the instrument model term or one of the models under it
+pub fn is_instrument_model(mut term: &str) -> bool {
+    loop {
+        if term == accession::INSTRUMENT_MODEL {
+            return true;
+        }
+        match INSTRUMENT_MODEL_PARENTS.binary_search_by(|(child, _)| (*child).cmp(term)) {
+            Ok(i) => term = INSTRUMENT_MODEL_PARENTS[i].1,
+            Err(_) => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_known_accessions() {
+        assert_eq!(lookup(accession::MZ_ARRAY), Some("m/z array"));
+        assert_eq!(lookup("UO:0000031"), Some("minute"));
+        assert_eq!(lookup("MS:9999999"), None);
+    }
+
+    #[test]
+    fn terms_are_sorted_for_binary_search() {
+        assert!(TERMS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn instrument_model_parents_are_sorted_for_binary_search() {
+        assert!(INSTRUMENT_MODEL_PARENTS
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn vendor_models_sit_under_the_instrument_model_branch() {
+        assert!(is_instrument_model(accession::INSTRUMENT_MODEL));
+        assert!(is_instrument_model(
+            accession::THERMO_FISHER_SCIENTIFIC_INSTRUMENT_MODEL
+        ));
+        assert!(is_instrument_model(accession::Q_EXACTIVE));
+        assert!(is_instrument_model(accession::TIMSTOF_PRO));
+        assert!(!is_instrument_model(accession::INSTRUMENT_SERIAL_NUMBER));
+        assert!(!is_instrument_model("MS:1000484"));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::accession;
+use crate::models::Spectrum;
+use crate::peakpick::{replace_peaks, take_array};
+
+/// Mass difference between the 13C and 12C isotopes, in daltons
+const ISOTOPE_SPACING: f64 = 1.003_354_8;
+
+impl Spectrum {
+    /// Collapses isotope envelopes into monoisotopic peaks, returning a deisotoped copy
+    ///
+    /// Starting from the lowest unassigned m/z, each charge from 1 to max_charge is tried by
+    /// following peaks spaced ISOTOPE_SPACING / charge apart, within tolerance_ppm of where
+    /// they are expected. The charge giving the longest envelope wins, ties going to the lower
+    /// charge. The envelope becomes one peak at its first m/z with the summed intensity.
+    ///
+    /// The inferred charges are written to a charge array (MS:1000516), with 0 for peaks that
+    /// are not part of an envelope; read them with charge_array. Other arrays are dropped.
+    pub fn deisotope(&self, tolerance_ppm: f64, max_charge: u8) -> Spectrum {
+        let mut deisotoped = self.clone();
+
+        // Step 1: walk the peaks in m/z order, whatever order the arrays are in
+        let mut peaks: Vec<(f64, f64)> = self.peaks().collect();
+        peaks.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut assigned = vec![false; peaks.len()];
+
+        // Step 2: grow the best envelope from each peak not already in one
+        let mut mz = Vec::new();
+        let mut intensity = Vec::new();
+        let mut charges = Vec::new();
+        for start in 0..peaks.len() {
+            if assigned[start] {
+                continue;
+            }
+            let mut best = (0, vec![start]);
+            for charge in 1..=max_charge {
+                let envelope = follow_envelope(&peaks, &assigned, start, charge, tolerance_ppm);
+                if envelope.len() > best.1.len() {
+                    best = (charge, envelope);
+                }
+            }
+
+            let (charge, envelope) = best;
+            for &i in &envelope {
+                assigned[i] = true;
+            }
+            mz.push(peaks[start].0);
+            intensity.push(envelope.iter().map(|&i| peaks[i].1).sum());
+            charges.push(f64::from(charge));
+        }
+
+        // Step 3: swap in the collapsed peaks and their charges
+        replace_peaks(&mut deisotoped, mz, intensity);
+        let mut charge_array = take_array(&mut deisotoped, accession::CHARGE_ARRAY, "charge array");
+        charge_array.decoded_data = Some(charges);
+        deisotoped.binary_data_arrays.push(charge_array);
+        deisotoped
+    }
+}
+
+/// Returns the positions of the isotope peaks following peaks[start] at the given charge
+fn follow_envelope(
+    peaks: &[(f64, f64)],
+    assigned: &[bool],
+    start: usize,
+    charge: u8,
+    tolerance_ppm: f64,
+) -> Vec<usize> {
+    let spacing = ISOTOPE_SPACING / f64::from(charge);
+    let mut envelope = vec![start];
+    let mut last = start;
+    loop {
+        let expected = peaks[last].0 + spacing;
+        let tolerance = expected * tolerance_ppm * 1e-6;
+
+        // Peaks are sorted, so only those after the last isotope can match
+        let next = (last + 1..peaks.len())
+            .take_while(|&i| peaks[i].0 <= expected + tolerance)
+            .filter(|&i| !assigned[i] && (peaks[i].0 - expected).abs() <= tolerance)
+            .min_by(|&a, &b| {
+                let distance = |i: usize| (peaks[i].0 - expected).abs();
+                distance(a).total_cmp(&distance(b))
+            });
+        match next {
+            Some(i) => {
+                envelope.push(i);
+                last = i;
+            }
+            None => return envelope,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ISOTOPE_SPACING;
+    use crate::models::Spectrum;
+
+    #[test]
+    fn collapses_a_doubly_charged_envelope() {
+        let mono = 600.3;
+        let envelope: Vec<f64> = (0..4)
+            .map(|i| mono + f64::from(i) * ISOTOPE_SPACING / 2.0)
+            .collect();
+        let spectrum = Spectrum::builder()
+            .peaks(
+                vec![
+                    envelope[0],
+                    envelope[1],
+                    envelope[2],
+                    envelope[3],
+                    450.0,
+                    700.0,
+                ],
+                vec![100.0, 80.0, 40.0, 10.0, 25.0, 5.0],
+            )
+            .build();
+
+        let deisotoped = spectrum.deisotope(10.0, 4);
+        assert_eq!(deisotoped.mz_array(), Some(&[450.0, mono, 700.0][..]));
+        assert_eq!(deisotoped.intensity_array(), Some(&[25.0, 230.0, 5.0][..]));
+        assert_eq!(deisotoped.charge_array(), Some(&[0.0, 2.0, 0.0][..]));
+    }
+}
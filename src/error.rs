@@ -0,0 +1,109 @@
+use std::path::PathBuf;
+
+/// Everything that can go wrong while reading or writing mzML
+#[derive(Debug, thiserror::Error)]
+pub enum MzmlError {
+    #[error("{error} (at byte {position})")]
+    AtPosition {
+        position: u64,
+        error: Box<MzmlError>,
+    },
+    #[error("Failed to read {}", .path.display())]
+    Open {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Xml(#[from] quick_xml::Error),
+    #[error("Missing attribute: {0}")]
+    MissingAttribute(String),
+    #[error("Invalid value for attribute {name}: {value:?}")]
+    InvalidAttribute { name: String, value: String },
+    #[error("Spectrum {spectrum_id}: {error}")]
+    InSpectrum {
+        spectrum_id: String,
+        error: Box<MzmlError>,
+    },
+    #[error("No <run> element found in the mzML file")]
+    MissingRun,
+    #[error("Failed to decode Base64")]
+    Base64(#[from] base64::DecodeError),
+    #[error("Failed to decompress binary data")]
+    Decompression(#[source] std::io::Error),
+    #[error("Unknown precision: {0}")]
+    UnknownPrecision(String),
+    #[error("Unknown compression: {0}")]
+    UnknownCompression(String),
+    #[error("Numpress data too short: need at least 8 header bytes, got {0}")]
+    NumpressTooShort(usize),
+    #[error("Failed to decode MS-Numpress: {0}")]
+    Numpress(String),
+    #[error(
+        "Spectrum {spectrum_id}: {array} has {actual} values but defaultArrayLength is {expected}"
+    )]
+    ArrayLengthMismatch {
+        spectrum_id: String,
+        array: String,
+        actual: usize,
+        expected: usize,
+    },
+    #[error("Invalid index: {0}")]
+    InvalidIndex(String),
+    #[cfg(feature = "polars")]
+    #[error(transparent)]
+    Polars(#[from] polars::prelude::PolarsError),
+    #[cfg(feature = "mzmlb")]
+    #[error(transparent)]
+    Hdf5(#[from] hdf5::Error),
+    #[error(transparent)]
+    CountMismatch(#[from] CountMismatch),
+    #[error("Spectrum not in index: {0}")]
+    SpectrumNotInIndex(String),
+    #[error("No spectrum found at offset {offset} for {id}")]
+    SpectrumNotAtOffset { id: String, offset: u64 },
+}
+
+impl MzmlError {
+    /// Attaches the byte offset in the source at which the error was detected
+    pub(crate) fn at(self, position: u64) -> Self {
+        match self {
+            MzmlError::AtPosition { .. } => self,
+            error => MzmlError::AtPosition {
+                position,
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Returns the byte offset the error was reported at, if known
+    pub fn position(&self) -> Option<u64> {
+        match self {
+            MzmlError::AtPosition { position, .. } => Some(*position),
+            _ => None,
+        }
+    }
+}
+
+/// A spectrum skipped by a lenient parse, and why
+#[derive(Debug)]
+pub struct SpectrumError {
+    /// The spectrum's id attribute, if it could be read
+    pub spectrum_id: Option<String>,
+    /// Position of the spectrum within the spectrumList
+    pub index: usize,
+    pub error: MzmlError,
+}
+
+/// A list element whose count attribute disagrees with the number of items it contains
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("<{element}> declares count {declared} but contains {actual}")]
+pub struct CountMismatch {
+    pub element: String,
+    pub declared: usize,
+    pub actual: usize,
+}
+
+pub type Result<T> = std::result::Result<T, MzmlError>;
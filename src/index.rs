@@ -0,0 +1,534 @@
+use crate::error::{MzmlError, Result};
+use crate::models::{CvParam, Run, Spectrum};
+use crate::options::{DecodeMode, ParseOptions};
+use crate::parser::{new_reader, MzmlParser};
+use crate::utils::{get_attr, get_attr_optional};
+use quick_xml::events::Event;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// How far back from the end of the file to look for <indexListOffset>
+const INDEX_LIST_OFFSET_WINDOW: u64 = 4096;
+
+/// Byte offsets of spectra and chromatograms from an indexedmzML <indexList>
+#[derive(Debug, Clone, Default)]
+pub struct MzmlIndex {
+    pub spectrum_offsets: Vec<(String, u64)>,
+    pub chromatogram_offsets: Vec<(String, u64)>,
+    spectrum_positions: HashMap<String, usize>,
+}
+
+impl MzmlIndex {
+    /// Reads the index of an indexedmzML source via its trailing <indexListOffset>
+    pub fn from_reader<R: Read + Seek>(source: &mut R) -> Result<Self> {
+        let index_list_offset = read_index_list_offset(source)?;
+        source.seek(SeekFrom::Start(index_list_offset))?;
+
+        let mut reader = new_reader(BufReader::new(source));
+        let mut buf = Vec::new();
+        let mut index = MzmlIndex::default();
+        let mut current_list = None;
+        let mut current_id = None;
+
+        loop {
+            let event = reader
+                .read_event_into(&mut buf)
+                .map_err(|e| MzmlError::from(e).at(index_list_offset + reader.buffer_position()))?;
+            match event {
+                Event::Start(ref e) => match e.name().as_ref() {
+                    b"index" => current_list = get_attr_optional(e, "name"),
+                    b"offset" => current_id = Some(get_attr(e, "idRef")?),
+                    _ => {}
+                },
+                Event::Text(ref t) => {
+                    if let Some(id) = current_id.take() {
+                        let offset = String::from_utf8_lossy(t).trim().parse().map_err(|_| {
+                            MzmlError::InvalidIndex(format!("invalid offset for {}", id))
+                        })?;
+                        match current_list.as_deref() {
+                            Some("spectrum") => {
+                                index
+                                    .spectrum_positions
+                                    .insert(id.clone(), index.spectrum_offsets.len());
+                                index.spectrum_offsets.push((id, offset));
+                            }
+                            Some("chromatogram") => index.chromatogram_offsets.push((id, offset)),
+                            _ => {}
+                        }
+                    }
+                }
+                Event::End(ref e) if e.name().as_ref() == b"indexList" => break,
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+        }
+
+        Ok(index)
+    }
+
+    /// Returns the byte offset of the spectrum with the given id
+    pub fn spectrum_offset(&self, id: &str) -> Option<u64> {
+        let position = *self.spectrum_positions.get(id)?;
+        Some(self.spectrum_offsets[position].1)
+    }
+}
+
+/// Finds the <indexListOffset> value near the end of the source
+fn read_index_list_offset<R: Read + Seek>(source: &mut R) -> Result<u64> {
+    let len = source.seek(SeekFrom::End(0))?;
+    source.seek(SeekFrom::Start(
+        len.saturating_sub(INDEX_LIST_OFFSET_WINDOW),
+    ))?;
+    let mut tail = Vec::new();
+    source.read_to_end(&mut tail)?;
+    let tail = String::from_utf8_lossy(&tail);
+
+    let start = tail.rfind("<indexListOffset>").ok_or_else(|| {
+        MzmlError::InvalidIndex(
+            "no <indexListOffset> found; is this an indexedmzML file?".to_string(),
+        )
+    })? + "<indexListOffset>".len();
+    let end = tail[start..]
+        .find("</indexListOffset>")
+        .ok_or_else(|| MzmlError::InvalidIndex("unterminated <indexListOffset>".to_string()))?
+        + start;
+
+    tail[start..end]
+        .trim()
+        .parse()
+        .map_err(|_| MzmlError::InvalidIndex("invalid <indexListOffset> value".to_string()))
+}
+
+/// Checks the SHA-1 stored in <fileChecksum> against the bytes of an indexedmzML document
+///
+/// The checksum covers everything from the start of the file through the <fileChecksum>
+/// start tag, as the indexedmzML schema specifies.
+pub fn verify_checksum(bytes: &[u8]) -> Result<bool> {
+    const OPEN: &[u8] = b"<fileChecksum>";
+    const CLOSE: &[u8] = b"</fileChecksum>";
+
+    let start = find_last(bytes, OPEN)
+        .ok_or_else(|| MzmlError::InvalidIndex("no <fileChecksum> found".to_string()))?
+        + OPEN.len();
+    let end = find_last(&bytes[start..], CLOSE)
+        .ok_or_else(|| MzmlError::InvalidIndex("unterminated <fileChecksum>".to_string()))?
+        + start;
+    let stored = String::from_utf8_lossy(&bytes[start..end]);
+
+    let digest = Sha1::digest(&bytes[..start]);
+    let computed: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(stored.trim().eq_ignore_ascii_case(&computed))
+}
+
+/// Returns the position of the last occurrence of needle in haystack
+fn find_last(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+/// Reads the referenceableParamGroups from the document header, stopping at <run>
+fn read_param_groups<R: Read + Seek>(source: &mut R) -> Result<HashMap<String, Vec<CvParam>>> {
+    source.seek(SeekFrom::Start(0))?;
+
+    let mut reader = new_reader(BufReader::new(source));
+    let mut buf = Vec::new();
+    let mut parser = MzmlParser::default();
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| MzmlError::from(e).at(reader.buffer_position()))?;
+        match &event {
+            Event::Start(e) if e.name().as_ref() == b"run" => break,
+            Event::Eof => break,
+            _ => {}
+        }
+        parser
+            .handle_event(&event)
+            .map_err(|e| e.at(reader.buffer_position()))?;
+        buf.clear();
+    }
+
+    Ok(parser.param_groups().clone())
+}
+
+/// Seek-based access to individual spectra of an indexedmzML source
+pub struct RandomAccessReader<R: Read + Seek> {
+    source: R,
+    index: MzmlIndex,
+    param_groups: HashMap<String, Vec<CvParam>>,
+}
+
+impl<R: Read + Seek> RandomAccessReader<R> {
+    /// Reads the index of the source so spectra can be fetched by id
+    pub fn new(mut source: R) -> Result<Self> {
+        let index = MzmlIndex::from_reader(&mut source)?;
+        let param_groups = read_param_groups(&mut source)?;
+        Ok(RandomAccessReader {
+            source,
+            index,
+            param_groups,
+        })
+    }
+
+    /// Returns the offset index read from the source
+    pub fn index(&self) -> &MzmlIndex {
+        &self.index
+    }
+
+    /// Seeks to and parses the single spectrum with the given id
+    pub fn get_spectrum_by_id(&mut self, id: &str) -> Result<Spectrum> {
+        let offset = self
+            .index
+            .spectrum_offset(id)
+            .ok_or_else(|| MzmlError::SpectrumNotInIndex(id.to_string()))?;
+        self.source.seek(SeekFrom::Start(offset))?;
+
+        let mut reader = new_reader(BufReader::new(&mut self.source));
+        let mut buf = Vec::new();
+        let mut parser = MzmlParser::with_param_groups(self.param_groups.clone());
+
+        loop {
+            let event = reader
+                .read_event_into(&mut buf)
+                .map_err(|e| MzmlError::from(e).at(offset + reader.buffer_position()))?;
+            if let Event::Eof = event {
+                return Err(MzmlError::SpectrumNotAtOffset {
+                    id: id.to_string(),
+                    offset,
+                });
+            }
+            let spectrum = parser
+                .handle_event(&event)
+                .map_err(|e| e.at(offset + reader.buffer_position()))?;
+            if let Some(spectrum) = spectrum {
+                return Ok(spectrum);
+            }
+            buf.clear();
+        }
+    }
+}
+
+impl RandomAccessReader<File> {
+    /// Opens an indexedmzML file for random access
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|source| MzmlError::Open {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::new(file)
+    }
+}
+
+/// A run whose spectrum metadata is held in memory while binary arrays stay in the source
+///
+/// Only the byte range of each <binary> block is kept, so memory grows with the number of
+/// spectra rather than the number of peaks; the cost is a seek and decode on every
+/// spectrum_data call. Chromatograms are small and are decoded up front.
+pub struct IndexedRun<R: Read + Seek> {
+    source: R,
+    run: Run,
+    binary_ranges: Vec<Vec<Option<(u64, u64)>>>,
+}
+
+impl<R: Read + Seek> IndexedRun<R> {
+    /// Parses the metadata of every spectrum, recording where its binary data lives
+    pub fn new(mut source: R) -> Result<Self> {
+        source.seek(SeekFrom::Start(0))?;
+
+        let mut reader = new_reader(BufReader::new(&mut source));
+        let mut buf = Vec::new();
+        let mut parser = MzmlParser::with_options(
+            ParseOptions::builder()
+                .decode_mode(DecodeMode::Lazy)
+                .build(),
+        );
+        let mut spectra = Vec::new();
+        let mut binary_ranges = Vec::new();
+        let mut spectrum_ranges = Vec::new();
+        let mut current_range = None;
+        let mut binary_start = 0;
+
+        loop {
+            // Step 1: remember where the event starts so </binary> marks the end of the text
+            let event_start = reader.buffer_position();
+            let event = reader
+                .read_event_into(&mut buf)
+                .map_err(|e| MzmlError::from(e).at(reader.buffer_position()))?;
+            match &event {
+                Event::Eof => break,
+                Event::Start(e) => match e.name().as_ref() {
+                    b"spectrum" | b"chromatogram" => spectrum_ranges.clear(),
+                    b"binaryDataArray" => current_range = None,
+                    b"binary" => binary_start = reader.buffer_position(),
+                    _ => {}
+                },
+                Event::Empty(e) => match e.name().as_ref() {
+                    b"spectrum" | b"chromatogram" => spectrum_ranges.clear(),
+                    b"binary" => {
+                        let end = reader.buffer_position();
+                        current_range = Some((end, end));
+                    }
+                    _ => {}
+                },
+                Event::End(e) => match e.name().as_ref() {
+                    b"binary" => current_range = Some((binary_start, event_start)),
+                    b"binaryDataArray" => spectrum_ranges.push(current_range.take()),
+                    _ => {}
+                },
+                _ => {}
+            }
+
+            // Step 2: keep the metadata but drop the encoded text, which is re-read on demand
+            let spectrum = parser
+                .handle_event(&event)
+                .map_err(|e| e.at(reader.buffer_position()))?;
+            if let Some(mut spectrum) = spectrum {
+                for array in &mut spectrum.binary_data_arrays {
+                    array.encoded_data = None;
+                }
+                spectra.push(spectrum);
+                binary_ranges.push(std::mem::take(&mut spectrum_ranges));
+            }
+            if let Some(count) = parser.take_spectrum_list_count() {
+                spectra.reserve(count);
+                binary_ranges.reserve(count);
+            }
+            buf.clear();
+        }
+
+        let mut run = parser.finish_document()?.run;
+        run.spectra = spectra;
+        for array in run
+            .chromatograms
+            .iter_mut()
+            .flat_map(|c| &mut c.binary_data_arrays)
+        {
+            array.decode()?;
+        }
+
+        Ok(IndexedRun {
+            source,
+            run,
+            binary_ranges,
+        })
+    }
+
+    /// Returns the run with spectrum metadata only; spectrum arrays are not decoded
+    pub fn run(&self) -> &Run {
+        &self.run
+    }
+
+    /// Returns the number of spectra in the run
+    pub fn len(&self) -> usize {
+        self.run.spectra.len()
+    }
+
+    /// Returns true if the run has no spectra
+    pub fn is_empty(&self) -> bool {
+        self.run.spectra.is_empty()
+    }
+
+    /// Reads and decodes the arrays of the spectrum at the given position in the run
+    pub fn spectrum_data(&mut self, index: usize) -> Result<Spectrum> {
+        let mut spectrum = self
+            .run
+            .spectra
+            .get(index)
+            .cloned()
+            .ok_or_else(|| MzmlError::SpectrumNotInIndex(index.to_string()))?;
+
+        for (array, range) in spectrum
+            .binary_data_arrays
+            .iter_mut()
+            .zip(&self.binary_ranges[index])
+        {
+            let Some((start, end)) = *range else {
+                continue;
+            };
+            self.source.seek(SeekFrom::Start(start))?;
+            let mut encoded = vec![0; (end - start) as usize];
+            self.source.read_exact(&mut encoded)?;
+            array.encoded_data = Some(String::from_utf8_lossy(&encoded).into_owned());
+            array.decode()?;
+        }
+
+        Ok(spectrum)
+    }
+}
+
+impl IndexedRun<File> {
+    /// Opens an uncompressed mzML file and parses its spectrum metadata
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|source| MzmlError::Open {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::new(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{indexed_mzml, mzml_with_spectra, spectrum};
+    use std::io::Cursor;
+
+    fn three_spectra() -> String {
+        indexed_mzml(&[
+            spectrum(0, 1, &[(100.0, 1.0)], ""),
+            spectrum(1, 2, &[(200.0, 2.0), (201.0, 3.0)], ""),
+            spectrum(2, 1, &[(300.0, 4.0)], ""),
+        ])
+    }
+
+    #[test]
+    fn reads_the_spectrum_offsets_from_the_index() {
+        let xml = three_spectra();
+
+        let index = MzmlIndex::from_reader(&mut Cursor::new(xml.as_bytes())).unwrap();
+        assert_eq!(index.spectrum_offsets.len(), 3);
+        let offset = index.spectrum_offset("scan=2").unwrap() as usize;
+        assert!(xml[offset..].starts_with(r#"<spectrum index="1" id="scan=2""#));
+        assert_eq!(index.spectrum_offset("scan=9"), None);
+    }
+
+    #[test]
+    fn fetches_a_single_spectrum_by_id() {
+        let mut reader =
+            RandomAccessReader::new(Cursor::new(three_spectra().into_bytes())).unwrap();
+
+        let spectrum = reader.get_spectrum_by_id("scan=2").unwrap();
+        assert_eq!(spectrum.index, 1);
+        assert_eq!(spectrum.ms_level(), Some(2));
+        assert_eq!(spectrum.mz_array(), Some(&[200.0, 201.0][..]));
+        assert!(matches!(
+            reader.get_spectrum_by_id("scan=9"),
+            Err(MzmlError::SpectrumNotInIndex(_))
+        ));
+    }
+
+    #[test]
+    fn a_plain_mzml_has_no_index() {
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[], "")]);
+        assert!(matches!(
+            RandomAccessReader::new(Cursor::new(xml.into_bytes())),
+            Err(MzmlError::InvalidIndex(_))
+        ));
+    }
+
+    #[test]
+    fn indexed_run_decodes_only_on_demand() {
+        let bad_binary = spectrum(1, 1, &[(200.0, 2.0)], "");
+        let (start, end) = (
+            bad_binary.find("<binary>").unwrap() + 8,
+            bad_binary.find("</binary>").unwrap(),
+        );
+        let bad_binary = format!(
+            "{}!!not base64!!{}",
+            &bad_binary[..start],
+            &bad_binary[end..]
+        );
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 1.0)], ""), bad_binary]);
+
+        // Parsing the metadata never decodes, so the invalid base64 goes unnoticed
+        let mut run = IndexedRun::new(Cursor::new(xml.into_bytes())).unwrap();
+        assert_eq!(run.len(), 2);
+        assert!(run
+            .run()
+            .spectra
+            .iter()
+            .flat_map(|s| &s.binary_data_arrays)
+            .all(|a| a.decoded_data.is_none() && a.encoded_data.is_none()));
+
+        let spectrum = run.spectrum_data(0).unwrap();
+        assert_eq!(spectrum.mz_array(), Some(&[100.0][..]));
+        assert!(matches!(run.spectrum_data(1), Err(MzmlError::Base64(_))));
+        assert!(run.spectrum_data(2).is_err());
+    }
+
+    #[test]
+    fn checksum_matches_an_intact_file() {
+        assert!(verify_checksum(three_spectra().as_bytes()).unwrap());
+
+        // Digest of the literal prefix computed independently with Python's hashlib
+        let literal = b"<indexedmzML><fileChecksum>073fe251d154c696e6d6b2b7fc931cbd32e4d3f9</fileChecksum></indexedmzML>";
+        assert!(verify_checksum(literal).unwrap());
+    }
+
+    #[test]
+    fn checksum_mismatch_is_detected() {
+        let corrupted = three_spectra().replacen("scan=2", "scan=9", 1);
+        assert!(!verify_checksum(corrupted.as_bytes()).unwrap());
+
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 1.0)], "")]);
+        assert!(verify_checksum(xml.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn indexed_run_caps_the_spectrum_list_count_hint() {
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 1.0)], "")]).replacen(
+            r#"<spectrumList count="1">"#,
+            r#"<spectrumList count="99999999999">"#,
+            1,
+        );
+
+        let indexed = IndexedRun::new(Cursor::new(xml.into_bytes())).unwrap();
+        assert_eq!(indexed.len(), 1);
+        assert!(indexed.run().spectra.capacity() <= 4096);
+        assert!(indexed.binary_ranges.capacity() <= 4096);
+    }
+
+    #[test]
+    fn truncated_indexed_file_reports_the_error_position() {
+        let xml = three_spectra();
+        // Cut inside the second <spectrum> start tag
+        let cut = xml.find(r#"<spectrum index="1""#).unwrap() + 10;
+
+        let Err(err) = IndexedRun::new(Cursor::new(xml.as_bytes()[..cut].to_vec())) else {
+            panic!("expected the truncated file to fail");
+        };
+        assert!(matches!(
+            &err,
+            MzmlError::AtPosition { error, .. } if matches!(**error, MzmlError::Xml(_))
+        ));
+        assert!(err.position().is_some());
+    }
+
+    #[test]
+    fn broken_spectrum_xml_is_positioned_from_its_offset() {
+        let xml = three_spectra();
+        let start = xml.find(r#"<spectrum index="1""#).unwrap();
+        let end = start + xml[start..].find("</spectrum>").unwrap();
+        // Same length, so the index offsets and the rest of the file still line up
+        let broken = format!("{}</spectrun>{}", &xml[..end], &xml[end + 11..]);
+
+        let mut reader = RandomAccessReader::new(Cursor::new(broken.into_bytes())).unwrap();
+        let err = reader.get_spectrum_by_id("scan=2").unwrap_err();
+        assert!(matches!(
+            &err,
+            MzmlError::AtPosition { error, .. } if matches!(**error, MzmlError::Xml(_))
+        ));
+        assert!(err.position().unwrap() > start as u64);
+        assert!(reader.get_spectrum_by_id("scan=1").is_ok());
+    }
+
+    #[test]
+    fn broken_index_list_is_positioned_in_the_file() {
+        let xml = three_spectra();
+        let index_start = xml.find("<indexList").unwrap();
+        let broken = xml.replacen("</index>", "</indez>", 1);
+
+        let err = MzmlIndex::from_reader(&mut Cursor::new(broken.as_bytes())).unwrap_err();
+        assert!(matches!(
+            &err,
+            MzmlError::AtPosition { error, .. } if matches!(**error, MzmlError::Xml(_))
+        ));
+        assert!(err.position().unwrap() > index_start as u64);
+    }
+}
@@ -1,106 +1,746 @@
+pub mod accession;
+pub mod builder;
+pub mod cv;
+pub mod deisotope;
+pub mod error;
+pub mod index;
 pub mod models;
+#[cfg(feature = "mzmlb")]
+pub mod mzmlb;
+#[cfg(feature = "mzxml")]
+pub mod mzxml;
+pub mod options;
+mod parser;
+pub mod peakpick;
+#[cfg(test)]
+mod test_fixtures;
 pub mod utils;
+pub mod writer;
 
-use anyhow::Result;
-use models::{BinaryDataArray, CvParam, Run, Scan, ScanList, ScanWindow, Spectrum};
+use error::{MzmlError, Result, SpectrumError};
+use flate2::read::GzDecoder;
+use models::{CvParamRef, MzML, Run, Spectrum};
+use options::ParseOptions;
+use parser::{new_reader_with_options, MzmlParser};
 use quick_xml::events::Event;
 use quick_xml::Reader;
-use utils::{decode_binary_data, get_attr, get_attr_optional};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Reads and parses an mzML file into a Run object, transparently handling gzip
+pub fn parse_mzml_file<P: AsRef<Path>>(path: P) -> Result<Run> {
+    parse_mzml_file_with_options(path, &ParseOptions::default())
+}
+
+/// Reads and parses an mzML file into a Run object using the given options
+pub fn parse_mzml_file_with_options<P: AsRef<Path>>(
+    path: P,
+    options: &ParseOptions,
+) -> Result<Run> {
+    parse_mzml_reader_with_options(open_mzml(path.as_ref())?, options)
+}
+
+/// Reads and parses a gzip-compressed .mzML.gz file into a Run object
+pub fn parse_mzml_gz<P: AsRef<Path>>(path: P) -> Result<Run> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|source| MzmlError::Open {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_mzml_reader(BufReader::new(GzDecoder::new(file)))
+}
+
+/// Opens an mzML file for buffered reading, decompressing it if it is gzipped
+fn open_mzml(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path).map_err(|source| MzmlError::Open {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let mut reader = BufReader::new(file);
+
+    // Sniff the gzip magic bytes without consuming them
+    let is_gzip = reader
+        .fill_buf()
+        .map_err(|source| MzmlError::Open {
+            path: path.to_path_buf(),
+            source,
+        })?
+        .starts_with(&[0x1f, 0x8b]);
+
+    if is_gzip {
+        Ok(Box::new(BufReader::new(GzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
 
 /// Parses an mzML string into a Run object
 pub fn parse_mzml(xml_data: &str) -> Result<Run> {
-    let mut reader = Reader::from_str(xml_data);
-    reader.config_mut().trim_text(true);
+    parse_mzml_reader(xml_data.as_bytes())
+}
+
+/// Parses an mzML string into a Run object using the given options
+pub fn parse_mzml_with_options(xml_data: &str, options: &ParseOptions) -> Result<Run> {
+    parse_mzml_reader_with_options(xml_data.as_bytes(), options)
+}
+
+/// Parses mzML bytes into a Run object without first validating them as UTF-8
+pub fn parse_mzml_bytes(data: &[u8]) -> Result<Run> {
+    parse_mzml_reader(data)
+}
+
+/// Parses mzML from a buffered reader into a Run object without loading it all up front
+pub fn parse_mzml_reader<R: BufRead>(reader: R) -> Result<Run> {
+    parse_mzml_reader_with_options(reader, &ParseOptions::default())
+}
+
+/// Parses mzML from a buffered reader into a Run object using the given options
+pub fn parse_mzml_reader_with_options<R: BufRead>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<Run> {
+    Ok(parse_mzml_document_reader_with_options(reader, options)?.run)
+}
+
+/// Parses an mzML string into an MzML document, keeping the metadata sections around the run
+pub fn parse_mzml_document(xml_data: &str) -> Result<MzML> {
+    parse_mzml_document_reader_with_options(xml_data.as_bytes(), &ParseOptions::default())
+}
 
+/// Reads and parses an mzML file into an MzML document, transparently handling gzip
+pub fn parse_mzml_document_file<P: AsRef<Path>>(path: P) -> Result<MzML> {
+    parse_mzml_document_reader_with_options(open_mzml(path.as_ref())?, &ParseOptions::default())
+}
+
+/// Parses mzML from a buffered reader into an MzML document using the given options
+pub fn parse_mzml_document_reader_with_options<R: BufRead>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<MzML> {
+    parse_with(reader, options, |_| true)
+}
+
+/// Parses an mzML string, keeping only the spectra the predicate accepts
+///
+/// Rejected spectra are dropped as soon as they are parsed, so their arrays are never held
+/// alongside the rest of the run.
+pub fn parse_mzml_filtered(xml_data: &str, predicate: impl Fn(&Spectrum) -> bool) -> Result<Run> {
+    let options = ParseOptions::default();
+    Ok(parse_with(xml_data.as_bytes(), &options, predicate)?.run)
+}
+
+/// Parses an mzML string, skipping spectra that fail to parse and returning why they failed
+///
+/// Errors outside of a <spectrum>, such as a missing <run>, still fail the whole parse, as
+/// does malformed XML anywhere, since the reader cannot resync after it.
+pub fn parse_mzml_lenient(xml_data: &str) -> Result<(Run, Vec<SpectrumError>)> {
+    let mut reader = new_reader_with_options(xml_data.as_bytes(), &ParseOptions::default());
     let mut buf = Vec::new();
-    let mut current_run = None;
+    let mut parser = MzmlParser::default();
     let mut spectra = Vec::new();
-    let mut current_spectrum = None;
-    let mut current_cv_params = Vec::new();
-    let mut current_binary_data_array = None;
-
-    while let Ok(event) = reader.read_event_into(&mut buf) {
-        match event {
-            Event::Start(ref e) => match e.name().as_ref() {
-                b"run" => {
-                    current_run = Some(Run {
-                        id: get_attr(e, "id")?,
-                        start_time: get_attr(e, "startTimeStamp")?,
-                        spectra: Vec::new(),
-                    });
+    let mut errors = Vec::new();
+    // (index, id) of the spectrum being read, and whether it is being skipped
+    let mut current = None;
+    let mut skipping = false;
+    let mut spectrum_count = 0;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| MzmlError::from(e).at(reader.buffer_position()))?;
+        match &event {
+            Event::Eof => break,
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"spectrum" => {
+                current = Some((spectrum_count, utils::get_attr_optional(e, "id")));
+                spectrum_count += 1;
+            }
+            Event::End(e) if e.name().as_ref() == b"spectrum" && skipping => {
+                skipping = false;
+                current = None;
+                buf.clear();
+                continue;
+            }
+            _ => {}
+        }
+
+        // Step 1: ignore the rest of a spectrum that already failed
+        if !skipping {
+            match parser.handle_event(&event) {
+                Ok(Some(spectrum)) => {
+                    spectra.push(spectrum);
+                    current = None;
                 }
-                b"spectrum" => {
-                    current_spectrum = Some(Spectrum {
-                        id: get_attr(e, "id")?,
-                        index: get_attr(e, "index")?.parse()?,
-                        default_array_length: get_attr(e, "defaultArrayLength")?.parse()?,
-                        cv_params: Vec::new(),
-                        scan_list: None,
-                        binary_data_arrays: Vec::new(),
-                    });
+                Ok(None) => {
+                    if let Some(count) = parser.take_spectrum_list_count() {
+                        spectra.reserve(count);
+                    }
                 }
-                b"binaryDataArray" => {
-                    current_binary_data_array = Some(BinaryDataArray {
-                        encoded_length: get_attr(e, "encodedLength")?.parse()?,
-                        cv_params: Vec::new(),
-                        decoded_data: None,
+                // Step 2: record errors inside a spectrum and skip to its end
+                Err(error) => {
+                    let error = error.at(reader.buffer_position());
+                    let Some((index, spectrum_id)) = current.take() else {
+                        return Err(error);
+                    };
+                    parser.abandon_spectrum();
+                    errors.push(SpectrumError {
+                        spectrum_id,
+                        index,
+                        error,
                     });
+                    // Nothing is left to skip if the spectrum failed on its own closing tag
+                    skipping = match &event {
+                        Event::End(e) => e.name().as_ref() != b"spectrum",
+                        Event::Empty(e) => e.name().as_ref() != b"spectrum",
+                        _ => true,
+                    };
                 }
-                b"binary" => {
-                    if let Some(array) = current_binary_data_array.as_mut() {
-                        let encoded_data = reader.read_text(e.name())?;
-                        let compression = array.cv_params.iter().find_map(|p| {
-                            if p.name.contains("compression") {
-                                p.name.clone().into()
-                            } else {
-                                None
-                            }
-                        });
-
-                        let precision = array.cv_params.iter().find_map(|p| {
-                            if p.name.contains("32-bit") || p.name.contains("64-bit") {
-                                p.name.clone().into()
-                            } else {
-                                None
-                            }
-                        });
-
-                        array.decoded_data = Some(decode_binary_data(
-                            &encoded_data,
-                            compression.as_deref(),
-                            precision.as_deref().unwrap_or("32-bit float"),
-                        )?);
-                    }
-                }
-                _ => {}
-            },
-            Event::End(ref e) => match e.name().as_ref() {
-                b"spectrum" => {
-                    if let Some(mut spectrum) = current_spectrum.take() {
-                        spectrum.cv_params = current_cv_params.clone();
-                        spectra.push(spectrum);
-                        current_cv_params.clear();
-                    }
+            }
+        }
+        buf.clear();
+    }
+
+    let mut run = parser.finish_document()?.run;
+    run.spectra = spectra;
+    Ok((run, errors))
+}
+
+/// Parses an mzML string, decoding binary arrays across the rayon thread pool
+#[cfg(feature = "rayon")]
+pub fn parse_mzml_parallel(xml_data: &str) -> Result<Run> {
+    let options = ParseOptions::builder()
+        .decode_mode(options::DecodeMode::Lazy)
+        .build();
+    let mut run = parse_mzml_with_options(xml_data, &options)?;
+    decode_all_parallel(&mut run)?;
+    Ok(run)
+}
+
+/// Decodes every still-encoded binary array of a run across the rayon thread pool
+#[cfg(feature = "rayon")]
+pub fn decode_all_parallel(run: &mut Run) -> Result<()> {
+    use rayon::prelude::*;
+
+    let spectrum_arrays = run
+        .spectra
+        .par_iter_mut()
+        .flat_map(|s| s.binary_data_arrays.par_iter_mut());
+    let chromatogram_arrays = run
+        .chromatograms
+        .par_iter_mut()
+        .flat_map(|c| c.binary_data_arrays.par_iter_mut());
+
+    spectrum_arrays
+        .chain(chromatogram_arrays)
+        .try_for_each(|array| array.decode())
+}
+
+/// Parses mzML from a tokio async reader into a Run object without blocking the runtime
+#[cfg(feature = "tokio")]
+pub async fn parse_mzml_async<R: tokio::io::AsyncBufRead + Unpin>(reader: R) -> Result<Run> {
+    parse_mzml_async_with_options(reader, &ParseOptions::default()).await
+}
+
+/// Parses mzML from a tokio async reader into a Run object using the given options
+#[cfg(feature = "tokio")]
+pub async fn parse_mzml_async_with_options<R: tokio::io::AsyncBufRead + Unpin>(
+    reader: R,
+    options: &ParseOptions,
+) -> Result<Run> {
+    let mut reader = new_reader_with_options(reader, options);
+    let mut parser = MzmlParser::with_options(options.clone());
+    let mut buf = Vec::new();
+    let mut spectra = Vec::new();
+
+    // Same loop as parse_with, awaiting each event instead of blocking on it
+    loop {
+        let event = reader
+            .read_event_into_async(&mut buf)
+            .await
+            .map_err(|e| MzmlError::from(e).at(reader.buffer_position()))?;
+        if let Event::Eof = event {
+            break;
+        }
+        let spectrum = parser
+            .handle_event(&event)
+            .map_err(|e| e.at(reader.buffer_position()))?;
+        spectra.extend(spectrum);
+        if let Some(count) = parser.take_spectrum_list_count() {
+            spectra.reserve(count);
+        }
+        buf.clear();
+    }
+
+    let mut run = parser.finish_document()?.run;
+    run.spectra = spectra;
+    Ok(run)
+}
+
+/// Drives a parser over every event of the reader and collects the document
+fn parse_with<R: BufRead>(
+    reader: R,
+    options: &ParseOptions,
+    keep: impl Fn(&Spectrum) -> bool,
+) -> Result<MzML> {
+    let mut reader = new_reader_with_options(reader, options);
+    let mut parser = MzmlParser::with_options(options.clone());
+    let mut buf = Vec::new();
+    let mut spectra = Vec::new();
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| MzmlError::from(e).at(reader.buffer_position()))?;
+        if let Event::Eof = event {
+            break;
+        }
+        let spectrum = parser
+            .handle_event(&event)
+            .map_err(|e| e.at(reader.buffer_position()))?;
+        if let Some(spectrum) = spectrum.filter(|s| keep(s)) {
+            spectra.push(spectrum);
+        }
+        if let Some(count) = parser.take_spectrum_list_count() {
+            spectra.reserve(count);
+        }
+        buf.clear();
+    }
+
+    let mut document = parser.finish_document()?;
+    document.run.spectra = spectra;
+    Ok(document)
+}
+
+/// Streams spectra one at a time from an mzML source
+pub struct SpectrumIterator<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    parser: MzmlParser,
+    done: bool,
+}
+
+impl<R: BufRead> SpectrumIterator<R> {
+    /// Creates an iterator over the spectra read from a buffered reader
+    pub fn from_reader(reader: R) -> Self {
+        Self::from_reader_with_options(reader, &ParseOptions::default())
+    }
+
+    /// Creates an iterator over the spectra read from a buffered reader using the given options
+    pub fn from_reader_with_options(reader: R, options: &ParseOptions) -> Self {
+        SpectrumIterator {
+            reader: new_reader_with_options(reader, options),
+            buf: Vec::new(),
+            parser: MzmlParser::with_options(options.clone()),
+            done: false,
+        }
+    }
+}
+
+impl SpectrumIterator<Box<dyn BufRead>> {
+    /// Creates an iterator over the spectra of an mzML file, transparently handling gzip
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::from_reader(open_mzml(path.as_ref())?))
+    }
+}
+
+impl<R: BufRead> Iterator for SpectrumIterator<R> {
+    type Item = Result<Spectrum>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            self.buf.clear();
+            let event = match self.reader.read_event_into(&mut self.buf) {
+                Ok(Event::Eof) => break,
+                Ok(event) => event,
+                Err(err) => {
+                    self.done = true;
+                    let err = MzmlError::from(err).at(self.reader.buffer_position());
+                    return Some(Err(err));
                 }
-                b"binaryDataArray" => {
-                    if let Some(array) = current_binary_data_array.take() {
-                        if let Some(spectrum) = current_spectrum.as_mut() {
-                            spectrum.binary_data_arrays.push(array);
-                        }
-                    }
+            };
+            match self.parser.handle_event(&event) {
+                Ok(Some(spectrum)) => return Some(Ok(spectrum)),
+                Ok(None) => {}
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err.at(self.reader.buffer_position())));
                 }
-                b"run" => {
-                    if let Some(run) = current_run.as_mut() {
-                        run.spectra = spectra.clone();
-                    }
+            }
+        }
+        self.done = true;
+        None
+    }
+}
+
+/// Returns borrowed views of every <cvParam> in an mzML string, in document order
+///
+/// Unlike the owned parse, no strings are copied, so the views cannot outlive xml_data. Use
+/// CvParamRef::to_cv_param to keep one.
+pub fn cv_param_refs(xml_data: &str) -> CvParamRefs<'_> {
+    CvParamRefs {
+        source: xml_data,
+        reader: Reader::from_str(xml_data),
+        done: false,
+    }
+}
+
+/// Iterator over borrowed <cvParam> views, created by cv_param_refs
+pub struct CvParamRefs<'a> {
+    source: &'a str,
+    reader: Reader<&'a [u8]>,
+    done: bool,
+}
+
+impl<'a> Iterator for CvParamRefs<'a> {
+    type Item = Result<CvParamRef<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let param = match self.reader.read_event() {
+                Ok(Event::Eof) => break,
+                Ok(Event::Start(e) | Event::Empty(e)) if e.name().as_ref() == b"cvParam" => {
+                    utils::parse_cv_param_ref(self.source, &e)
                 }
-                _ => {}
-            },
-            Event::Eof => break,
+                Ok(_) => continue,
+                Err(err) => Err(MzmlError::from(err)),
+            };
+            if param.is_err() {
+                self.done = true;
+            }
+            return Some(param.map_err(|e| e.at(self.reader.buffer_position())));
+        }
+        self.done = true;
+        None
+    }
+}
+
+/// Returns the source XML of the spectrum at the given position in document order, from
+/// <spectrum> through </spectrum>, without parsing the spectra
+pub fn extract_spectrum_xml(source: &str, index: usize) -> Option<&str> {
+    let mut position = 0;
+    find_spectrum_xml(source, |_| {
+        position += 1;
+        position - 1 == index
+    })
+}
+
+/// Returns the source XML of the spectrum with the given native id, without parsing the spectra
+pub fn extract_spectrum_xml_by_id<'a>(source: &'a str, id: &str) -> Option<&'a str> {
+    find_spectrum_xml(source, |e| {
+        utils::get_attr_bytes(e, "id").is_some_and(|v| v.as_ref() == id.as_bytes())
+    })
+}
+
+/// Returns the source XML of the first <spectrum> element the predicate accepts
+fn find_spectrum_xml(
+    source: &str,
+    mut matches: impl FnMut(&quick_xml::events::BytesStart) -> bool,
+) -> Option<&str> {
+    let mut reader = Reader::from_str(source);
+    loop {
+        let start = reader.buffer_position() as usize;
+        match reader.read_event().ok()? {
+            Event::Eof => return None,
+            Event::Empty(e) if e.name().as_ref() == b"spectrum" && matches(&e) => {
+                return source.get(start..reader.buffer_position() as usize);
+            }
+            Event::Start(e) if e.name().as_ref() == b"spectrum" && matches(&e) => {
+                reader.read_to_end(e.name()).ok()?;
+                return source.get(start..reader.buffer_position() as usize);
+            }
             _ => {}
         }
-        buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{mzml_with_spectra, spectrum, temp_file};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn parses_a_file_from_its_path() {
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 5.0)], "")]);
+        let path = temp_file("parse_file.mzML", xml.as_bytes());
+
+        let run = parse_mzml_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(run.spectra.len(), 1);
+        assert_eq!(run.spectra[0].mz_array(), Some(&[100.0][..]));
+    }
+
+    #[test]
+    fn gzipped_file_parses_like_the_plain_one() {
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 5.0), (101.0, 6.0)], ""),
+            spectrum(1, 2, &[(50.0, 1.0)], ""),
+        ]);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(xml.as_bytes()).unwrap();
+        let path = temp_file("parse_gz.mzML.gz", &encoder.finish().unwrap());
+
+        let plain = parse_mzml(&xml).unwrap();
+        let gz = parse_mzml_gz(&path).unwrap();
+        let sniffed = parse_mzml_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(gz, plain);
+        assert_eq!(sniffed, plain);
+    }
+
+    /// A spectrum that ends with its <scanList> and <scan> still open
+    fn broken_xml() -> String {
+        mzml_with_spectra(&[spectrum(
+            0,
+            1,
+            &[(100.0, 5.0)],
+            "<scanList count=\"1\"><scan>",
+        )])
+    }
+
+    #[test]
+    fn broken_xml_reports_its_position() {
+        let error = parse_mzml(&broken_xml()).unwrap_err();
+        assert!(matches!(
+            &error,
+            MzmlError::AtPosition { error, .. } if matches!(**error, MzmlError::Xml(_))
+        ));
+        assert!(error.position().unwrap() > 0);
+    }
+
+    #[test]
+    fn spectrum_iterator_yields_a_read_error_once() {
+        let xml = broken_xml();
+        let mut spectra = SpectrumIterator::from_reader(xml.as_bytes());
+
+        let error = spectra.next().unwrap().unwrap_err();
+        assert!(error.position().unwrap() > 0);
+        assert!(spectra.next().is_none());
+    }
+
+    #[test]
+    fn lenient_parse_skips_a_bad_spectrum() {
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 5.0)], "")
+                .replace(r#"defaultArrayLength="1""#, r#"defaultArrayLength="one""#),
+            spectrum(1, 1, &[(200.0, 6.0)], ""),
+        ]);
+
+        let (run, errors) = parse_mzml_lenient(&xml).unwrap();
+        assert_eq!(run.spectra.len(), 1);
+        assert_eq!(run.spectra[0].id, "scan=2");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].index, 0);
+        assert_eq!(errors[0].spectrum_id.as_deref(), Some("scan=1"));
+    }
+
+    #[test]
+    fn lenient_parse_fails_on_broken_xml() {
+        let error = parse_mzml_lenient(&broken_xml()).unwrap_err();
+        assert!(error.position().unwrap() > 0);
     }
 
-    current_run.ok_or_else(|| anyhow::anyhow!("No <run> element found in the mzML file"))
+    #[test]
+    fn spectrum_list_count_is_only_a_capacity_hint() {
+        let spectra = [
+            spectrum(0, 1, &[(100.0, 1.0)], ""),
+            spectrum(1, 2, &[(50.0, 2.0)], ""),
+        ];
+        let xml = mzml_with_spectra(&spectra);
+        let run = parse_mzml(&xml).unwrap();
+        assert_eq!(run.spectra.len(), 2);
+        assert!(run.spectra.capacity() >= 2);
+
+        // A huge or malformed count must not be trusted for the allocation
+        for count in ["99999999999", "18446744073709551615", "many"] {
+            let xml = xml.replace(
+                r#"<spectrumList count="2">"#,
+                &format!(r#"<spectrumList count="{count}">"#),
+            );
+            let run = parse_mzml(&xml).unwrap();
+            assert_eq!(run.spectra.len(), 2);
+            assert!(run.spectra.capacity() <= 4096, "{}", run.spectra.capacity());
+        }
+    }
+
+    #[test]
+    fn truncated_document_keeps_the_spectra_read() {
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 1.0)], ""),
+            spectrum(1, 2, &[(50.0, 2.0)], ""),
+        ]);
+        let truncated = &xml[..xml.find("</spectrumList>").unwrap()];
+
+        let run = parse_mzml(truncated).unwrap();
+        assert_eq!(run.spectra.len(), 2);
+        assert_eq!(run.spectra[1].mz_array(), Some(&[50.0][..]));
+    }
+
+    #[test]
+    fn predicate_drops_ms2_spectra() {
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 1.0)], ""),
+            spectrum(1, 2, &[(50.0, 2.0)], ""),
+            spectrum(2, 2, &[(60.0, 3.0)], ""),
+            spectrum(3, 1, &[(110.0, 4.0)], ""),
+        ]);
+
+        let run = parse_mzml_filtered(&xml, |s| s.ms_level() == Some(1)).unwrap();
+        let ids: Vec<&str> = run.spectra.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["scan=1", "scan=4"]);
+        assert!(run.spectra.iter().all(|s| s.ms_level() == Some(1)));
+    }
+
+    /// Counts the allocations made on the current thread, so parallel tests don't interfere
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            std::alloc::System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations_during(f: impl FnOnce()) -> usize {
+        let before = ALLOCATIONS.with(|count| count.get());
+        f();
+        ALLOCATIONS.with(|count| count.get()) - before
+    }
+
+    #[test]
+    fn borrowed_cv_params_do_not_allocate_per_param() {
+        let spectra: Vec<String> = (0..200)
+            .map(|i| spectrum(i, 1, &[(100.0 + i as f64, 1.0)], ""))
+            .collect();
+        let xml = mzml_with_spectra(&spectra);
+
+        let mut views = 0;
+        let borrowed = allocations_during(|| {
+            for param in cv_param_refs(&xml) {
+                let param = param.unwrap();
+                // Every view is a slice of the input, not a copy
+                assert!(xml
+                    .as_bytes()
+                    .as_ptr_range()
+                    .contains(&param.accession.as_ptr()));
+                views += 1;
+            }
+        });
+        let owned = allocations_during(|| {
+            let params: Vec<crate::models::CvParam> = cv_param_refs(&xml)
+                .map(|param| param.unwrap().to_cv_param())
+                .collect();
+            assert_eq!(params.len(), views);
+        });
+
+        // Two spectrum params and three params on each of the two arrays
+        assert_eq!(views, 200 * 8);
+        assert!(borrowed < 10, "{borrowed} allocations for {views} views");
+        assert!(owned >= 3 * views, "{owned} allocations for {views} params");
+    }
+
+    #[test]
+    fn parses_a_byte_slice() {
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 5.0)], "")]);
+        // A stray Latin-1 byte in a userParam value makes the document invalid UTF-8
+        let mut bytes = xml.into_bytes();
+        let insert_at = bytes
+            .windows(12)
+            .position(|w| w == b"<binaryDataA")
+            .unwrap();
+        let user_param = b"<userParam name=\"operator\" value=\"Andr\xe9\"/>";
+        bytes.splice(insert_at..insert_at, user_param.iter().copied());
+        assert!(std::str::from_utf8(&bytes).is_err());
+
+        let run = parse_mzml_bytes(&bytes).unwrap();
+        assert_eq!(run.spectra.len(), 1);
+        assert_eq!(run.spectra[0].mz_array(), Some(&[100.0][..]));
+        assert_eq!(run.spectra[0].user_params[0].name, "operator");
+    }
+
+    #[cfg(feature = "tokio")]
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn parses_from_an_async_cursor() {
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 5.0)], ""),
+            spectrum(1, 2, &[(50.0, 1.0), (60.0, 2.0)], ""),
+        ]);
+        let cursor = std::io::Cursor::new(xml.into_bytes());
+
+        let run = block_on(parse_mzml_async(cursor)).unwrap();
+        assert_eq!(run.spectra.len(), 2);
+        assert_eq!(run.spectra[1].mz_array(), Some(&[50.0, 60.0][..]));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_parse_reports_broken_xml() {
+        let cursor = std::io::Cursor::new(broken_xml().into_bytes());
+
+        let err = block_on(parse_mzml_async(cursor)).unwrap_err();
+        assert!(err.position().is_some());
+    }
+
+    #[test]
+    fn extracts_the_second_spectrum_xml() {
+        let spectra = [
+            spectrum(0, 1, &[(100.0, 5.0)], ""),
+            spectrum(1, 2, &[(50.0, 1.0)], ""),
+            spectrum(2, 1, &[(110.0, 3.0)], ""),
+        ];
+        let xml = mzml_with_spectra(&spectra);
+
+        let second = extract_spectrum_xml(&xml, 1).unwrap();
+        assert_eq!(second, spectra[1].trim_end());
+        assert_eq!(extract_spectrum_xml_by_id(&xml, "scan=2"), Some(second));
+        assert!(extract_spectrum_xml(&xml, 3).is_none());
+        assert!(extract_spectrum_xml_by_id(&xml, "scan=9").is_none());
+
+        // The extract parses on its own once wrapped in a document
+        let run = parse_mzml(&mzml_with_spectra(&[second.to_string()])).unwrap();
+        assert_eq!(run.spectra[0].mz_array(), Some(&[50.0][..]));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_decode_matches_the_sequential_parse() {
+        let spectra: Vec<String> = (0..50)
+            .map(|i| {
+                let peaks: Vec<(f64, f64)> = (0..20)
+                    .map(|p| (100.0 + i as f64 + p as f64 * 0.1, p as f64))
+                    .collect();
+                spectrum(i, 1, &peaks, "")
+            })
+            .collect();
+        let xml = mzml_with_spectra(&spectra);
+
+        let parallel = parse_mzml_parallel(&xml).unwrap();
+        assert_eq!(parallel, parse_mzml(&xml).unwrap());
+        assert!(parallel
+            .spectra
+            .iter()
+            .flat_map(|s| &s.binary_data_arrays)
+            .all(|a| a.encoded_data.is_none() && a.decoded_data.is_some()));
+    }
 }
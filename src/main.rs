@@ -1,11 +1,13 @@
-use anyhow::Result;
+use libms::error::Result;
 use libms::models::Run;
-use libms::parse_mzml;
+use libms::parse_mzml_file;
 
 fn main() -> Result<()> {
-    // Example: Load mzML data from a string (you can replace this with file reading)
-    let mzml_data = include_str!("../sample01.mzML");
-    let run: Run = parse_mzml(mzml_data)?;
+    // Example: Load mzML data from a file (defaults to sample01.mzML)
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "sample01.mzML".to_string());
+    let run: Run = parse_mzml_file(&path)?;
 
     // Output parsed data
     println!("Successfully parsed mzML file!");
@@ -13,7 +15,7 @@ fn main() -> Result<()> {
     println!("Start Time: {}", run.start_time);
     println!("\nFound {} spectra", run.spectra.len());
 
-    for (i, spectrum) in run.spectra.iter().take(5).enumerate() {
+    for (i, spectrum) in run.first_n(5).into_iter().enumerate() {
         println!("\nSpectrum #{}", i + 1);
         println!("  ID: {}", spectrum.id);
         println!("  Index: {}", spectrum.index);
@@ -1,52 +1,1993 @@
-#[derive(Debug, Clone)]
+use crate::accession;
+use crate::cv;
+use crate::error::{CountMismatch, MzmlError, Result};
+use crate::utils::{decode_binary_data_with_codecs, ByteOrder, Codec, Precision};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+/// A whole mzML document: the run plus the document-level metadata sections
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MzML {
+    pub cv_list: Vec<Cv>,
+    pub samples: Vec<Sample>,
+    pub software: Vec<Software>,
+    pub instrument_configurations: Vec<InstrumentConfiguration>,
+    pub data_processing: Vec<DataProcessing>,
+    pub run: Run,
+    /// cvParam accessions cv::lookup does not know, collected if ParseOptions asks for them
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub unknown_accessions: HashSet<String>,
+    /// Lists whose count attribute was wrong, collected if ParseOptions asks for lenient checks
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub count_mismatches: Vec<CountMismatch>,
+}
+
+impl MzML {
+    /// Returns the instrument model named by the first instrument configuration
+    ///
+    /// Only terms in the instrument model branch that cv::is_instrument_model knows are read,
+    /// so a model missing from that table gives None. The generic "instrument model" term
+    /// carries the model in its value.
+    pub fn instrument_model(&self) -> Option<&str> {
+        let param = self
+            .instrument_configurations
+            .first()?
+            .cv_params
+            .iter()
+            .find(|p| cv::is_instrument_model(&p.accession))?;
+        match param.value.as_deref() {
+            Some(value)
+                if &*param.accession == accession::INSTRUMENT_MODEL && !value.is_empty() =>
+            {
+                Some(value)
+            }
+            _ => Some(&param.name),
+        }
+    }
+
+    /// Returns the sample the run's sampleRef points at
+    pub fn run_sample(&self) -> Option<&Sample> {
+        let sample_ref = self.run.sample_ref.as_deref()?;
+        self.samples.iter().find(|s| s.id == sample_ref)
+    }
+
+    /// Returns the instrument serial number of the first instrument configuration that has one
+    pub fn instrument_serial_number(&self) -> Option<&str> {
+        self.instrument_configurations.iter().find_map(|c| {
+            find_cv_param(&c.cv_params, accession::INSTRUMENT_SERIAL_NUMBER)?
+                .value
+                .as_deref()
+        })
+    }
+}
+
+/// A controlled vocabulary declared in <cvList>
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Cv {
+    pub id: String,
+    pub full_name: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub version: Option<String>,
+    pub uri: String,
+}
+
+/// A sample declared in <sampleList>, e.g. one well of a plate
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Sample {
+    pub id: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub name: Option<String>,
+    pub cv_params: Vec<CvParam>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Software {
+    pub id: String,
+    pub version: String,
+    pub cv_params: Vec<CvParam>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InstrumentConfiguration {
+    pub id: String,
+    pub cv_params: Vec<CvParam>,
+    pub components: Vec<Component>,
+}
+
+/// One entry of an instrument configuration's <componentList>
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Component {
+    pub component_type: ComponentType,
+    pub order: u32,
+    pub cv_params: Vec<CvParam>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ComponentType {
+    Source,
+    Analyzer,
+    Detector,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DataProcessing {
+    pub id: String,
+    pub processing_methods: Vec<ProcessingMethod>,
+}
+
+/// One processing step, e.g. peak picking or conversion, and the software that ran it
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProcessingMethod {
+    pub order: u32,
+    pub software_ref: String,
+    pub cv_params: Vec<CvParam>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Run {
     pub id: String,
     pub start_time: String,
+    /// Id of the sample in MzML::samples that the run acquired, if given
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub sample_ref: Option<String>,
+    pub file_description: Option<FileDescription>,
     pub spectra: Vec<Spectrum>,
+    pub chromatograms: Vec<Chromatogram>,
+}
+
+impl Run {
+    /// Returns the spectrum with the given native id
+    pub fn get_spectrum_by_id(&self, id: &str) -> Option<&Spectrum> {
+        self.spectra.iter().find(|s| s.id == id)
+    }
+
+    /// Returns the spectrum with the given index attribute
+    pub fn get_spectrum_by_index(&self, index: usize) -> Option<&Spectrum> {
+        // Spectra are normally stored in index order, so try the direct position first
+        match self.spectra.get(index) {
+            Some(spectrum) if spectrum.index == index => Some(spectrum),
+            _ => self.spectra.iter().find(|s| s.index == index),
+        }
+    }
+
+    /// Borrows the spectra at the given positions, clamping the range to the spectra present
+    pub fn slice_spectra(&self, range: Range<usize>) -> Vec<&Spectrum> {
+        let end = range.end.min(self.spectra.len());
+        let start = range.start.min(end);
+        self.spectra[start..end].iter().collect()
+    }
+
+    /// Borrows up to the first n spectra
+    pub fn first_n(&self, n: usize) -> Vec<&Spectrum> {
+        self.slice_spectra(0..n)
+    }
+
+    /// Iterates over the spectra with the given MS level
+    pub fn spectra_by_ms_level(&self, level: u8) -> impl Iterator<Item = &Spectrum> {
+        self.spectra
+            .iter()
+            .filter(move |s| s.ms_level() == Some(level))
+    }
+
+    /// Builds a DataFrame with one row of summary accessors per spectrum
+    #[cfg(feature = "polars")]
+    pub fn to_summary_dataframe(&self) -> Result<polars::prelude::DataFrame> {
+        use polars::prelude::{Column, DataFrame};
+
+        let spectra = &self.spectra;
+        let column = |name: &str, values: Vec<Option<f64>>| Column::new(name.into(), values);
+        let columns = vec![
+            Column::new(
+                "index".into(),
+                spectra.iter().map(|s| s.index as u64).collect::<Vec<_>>(),
+            ),
+            Column::new(
+                "id".into(),
+                spectra.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(),
+            ),
+            Column::new(
+                "ms_level".into(),
+                spectra
+                    .iter()
+                    .map(|s| s.ms_level().map(u32::from))
+                    .collect::<Vec<_>>(),
+            ),
+            column(
+                "retention_time",
+                spectra.iter().map(Spectrum::retention_time).collect(),
+            ),
+            column(
+                "tic",
+                spectra.iter().map(Spectrum::total_ion_current).collect(),
+            ),
+            column(
+                "base_peak_mz",
+                spectra.iter().map(Spectrum::base_peak_mz).collect(),
+            ),
+            column(
+                "precursor_mz",
+                spectra.iter().map(Spectrum::precursor_mz).collect(),
+            ),
+            Column::new(
+                "num_peaks".into(),
+                spectra
+                    .iter()
+                    .map(|s| s.peak_count() as u64)
+                    .collect::<Vec<_>>(),
+            ),
+        ];
+        Ok(DataFrame::new(spectra.len(), columns)?)
+    }
+
+    /// Counts spectra per MS level, skipping spectra without one
+    pub fn ms_level_counts(&self) -> BTreeMap<u8, usize> {
+        let mut counts = BTreeMap::new();
+        for level in self.spectra.iter().filter_map(Spectrum::ms_level) {
+            *counts.entry(level).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Returns the earliest and latest retention time across the spectra, in seconds
+    pub fn retention_time_range(&self) -> Option<(f64, f64)> {
+        self.spectra
+            .iter()
+            .filter_map(Spectrum::retention_time)
+            .fold(None, |range, rt| match range {
+                None => Some((rt, rt)),
+                Some((min, max)) => Some((f64::min(min, rt), f64::max(max, rt))),
+            })
+    }
+
+    /// Returns the MS1 spectrum whose retention time is nearest the given seconds
+    ///
+    /// Ties go to the earlier spectrum. Ascending retention times are binary searched; spectra
+    /// out of order fall back to a linear scan.
+    pub fn spectrum_at_rt(&self, seconds: f64) -> Option<&Spectrum> {
+        let timed: Vec<(f64, &Spectrum)> = self
+            .spectra_by_ms_level(1)
+            .filter_map(|s| Some((s.retention_time()?, s)))
+            .collect();
+        if !timed.windows(2).all(|pair| pair[0].0 <= pair[1].0) {
+            return timed
+                .iter()
+                .min_by(|(a, _), (b, _)| (a - seconds).abs().total_cmp(&(b - seconds).abs()))
+                .map(|&(_, s)| s);
+        }
+
+        // Step 1: find the first time at or after the target and compare it with the one before
+        let after = timed.partition_point(|&(rt, _)| rt < seconds);
+        let nearest = match (after.checked_sub(1), timed.get(after)) {
+            (None, _) => after,
+            (Some(before), None) => before,
+            (Some(before), Some(&(rt_after, _))) => {
+                if seconds - timed[before].0 <= rt_after - seconds {
+                    before
+                } else {
+                    after
+                }
+            }
+        };
+
+        // Step 2: among equal times, the earliest spectrum wins
+        let rt = timed.get(nearest)?.0;
+        let first = timed.partition_point(|&(other, _)| other < rt);
+        Some(timed[first].1)
+    }
+
+    /// Returns the spectra of any MS level with min <= retention time <= max, in seconds
+    pub fn spectra_in_rt_range(&self, min: f64, max: f64) -> Vec<&Spectrum> {
+        self.spectra
+            .iter()
+            .filter(|s| s.retention_time().is_some_and(|rt| min <= rt && rt <= max))
+            .collect()
+    }
+
+    /// Stably sorts the spectra by ascending retention time, putting those without one last
+    ///
+    /// Each spectrum keeps its index attribute, so get_spectrum_by_index still finds it.
+    pub fn sort_by_retention_time(&mut self) {
+        let mut keyed: Vec<(Option<f64>, Spectrum)> = self
+            .spectra
+            .drain(..)
+            .map(|s| (s.retention_time(), s))
+            .collect();
+        keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+            (Some(a), Some(b)) => a.total_cmp(b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        });
+        self.spectra = keyed.into_iter().map(|(_, s)| s).collect();
+    }
+
+    /// Sums the MS1 intensity within tolerance_ppm of mz, as (retention time, intensity) pairs
+    ///
+    /// Pairs follow spectrum order, and MS1 spectra without a retention time are left out.
+    pub fn extract_ion_chromatogram(&self, mz: f64, tolerance_ppm: f64) -> Vec<(f64, f64)> {
+        let tolerance = mz * tolerance_ppm * 1e-6;
+        self.spectra_by_ms_level(1)
+            .filter_map(|s| {
+                let intensity = s
+                    .peaks_in_range(mz - tolerance, mz + tolerance)
+                    .iter()
+                    .map(|&(_, intensity)| intensity)
+                    .sum();
+                Some((s.retention_time()?, intensity))
+            })
+            .collect()
+    }
+
+    /// Returns the total ion current of each MS1 spectrum as (retention time, intensity) pairs
+    ///
+    /// Uses the total ion current cvParam where present and the summed intensity array otherwise.
+    pub fn total_ion_chromatogram(&self) -> Vec<(f64, f64)> {
+        self.ms1_trace(Spectrum::total_ion_current)
+    }
+
+    /// Returns the base peak intensity of each MS1 spectrum as (retention time, intensity) pairs
+    ///
+    /// Uses the base peak intensity cvParam where present and the tallest peak otherwise.
+    pub fn base_peak_chromatogram(&self) -> Vec<(f64, f64)> {
+        self.ms1_trace(|s| {
+            s.base_peak_intensity()
+                .or_else(|| s.base_peak().map(|(_, intensity)| intensity))
+        })
+    }
+
+    /// Pairs each MS1 spectrum's retention time with a value, skipping spectra lacking either
+    fn ms1_trace(&self, value: impl Fn(&Spectrum) -> Option<f64>) -> Vec<(f64, f64)> {
+        self.spectra_by_ms_level(1)
+            .filter_map(|s| Some((s.retention_time()?, value(s)?)))
+            .collect()
+    }
+}
+
+/// The document's <fileDescription>: what it contains and where it came from
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FileDescription {
+    pub file_content: Vec<CvParam>,
+    pub source_files: Vec<SourceFile>,
+}
+
+/// A <sourceFile> the document was converted from, e.g. a vendor RAW file
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SourceFile {
+    pub id: String,
+    pub name: String,
+    pub location: String,
+    pub cv_params: Vec<CvParam>,
+}
+
+impl SourceFile {
+    /// Returns the SHA-1 checksum (MS:1000569) recorded for the source file
+    pub fn sha1(&self) -> Option<&str> {
+        find_cv_param(&self.cv_params, accession::SHA1)?
+            .value
+            .as_deref()
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Spectrum {
     pub id: String,
     pub index: usize,
     pub default_array_length: usize,
+    /// Id of the sourceFile this spectrum was read from, if not the default
+    pub source_file_ref: Option<String>,
+    /// Id of the dataProcessing applied to this spectrum, if not the spectrumList default
+    pub data_processing_ref: Option<String>,
     pub cv_params: Vec<CvParam>,
+    pub user_params: Vec<UserParam>,
     pub scan_list: Option<ScanList>,
+    pub precursors: Vec<Precursor>,
     pub binary_data_arrays: Vec<BinaryDataArray>,
+    /// Values derived from the arrays, computed on first use
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cache: SpectrumCache,
+}
+
+/// Memoized values derived from a spectrum's arrays
+///
+/// Cloning gives an empty cache, and the cache is ignored when comparing spectra. Call
+/// Spectrum::clear_cache after changing the arrays in place.
+#[derive(Debug, Default)]
+pub struct SpectrumCache {
+    intensity_stats: OnceLock<IntensityStats>,
 }
 
-#[derive(Debug, Clone)]
+impl Clone for SpectrumCache {
+    fn clone(&self) -> Self {
+        SpectrumCache::default()
+    }
+}
+
+impl PartialEq for SpectrumCache {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Spectrum {
+    /// Returns the MS level (MS:1000511) of the spectrum
+    pub fn ms_level(&self) -> Option<u8> {
+        parse_cv_value(&self.cv_params, accession::MS_LEVEL)
+    }
+
+    /// Returns the scan start time (MS:1000016) in seconds
+    pub fn retention_time(&self) -> Option<f64> {
+        let param = self
+            .scan_list
+            .as_ref()?
+            .scans
+            .iter()
+            .find_map(|scan| find_cv_param(&scan.cv_params, accession::SCAN_START_TIME))?;
+        // A missing or unrecognised unit is taken to be seconds
+        param
+            .value_in_unit(accession::SECOND)
+            .or_else(|| param.value_as_f64())
+    }
+
+    /// Returns the instrumentConfigurationRef of the first scan that has one
+    pub fn instrument_configuration_ref(&self) -> Option<&str> {
+        self.scan_list
+            .as_ref()?
+            .scans
+            .iter()
+            .find_map(|scan| scan.instrument_configuration_ref.as_deref())
+    }
+
+    /// Returns the scan filter string (MS:1000512), falling back to a userParam of that name
+    pub fn filter_string(&self) -> Option<&str> {
+        let scans = &self.scan_list.as_ref()?.scans;
+        scans
+            .iter()
+            .find_map(|scan| find_cv_param(&scan.cv_params, accession::FILTER_STRING))
+            .and_then(|p| p.value.as_deref())
+            .or_else(|| {
+                scans
+                    .iter()
+                    .flat_map(|scan| &scan.user_params)
+                    .find(|p| p.name == "filter string")?
+                    .value
+                    .as_deref()
+            })
+    }
+
+    /// Returns the decoded m/z array (MS:1000514)
+    pub fn mz_array(&self) -> Option<&[f64]> {
+        find_array(&self.binary_data_arrays, accession::MZ_ARRAY)
+    }
+
+    /// Returns the decoded intensity array (MS:1000515)
+    pub fn intensity_array(&self) -> Option<&[f64]> {
+        find_array(&self.binary_data_arrays, accession::INTENSITY_ARRAY)
+    }
+
+    /// Returns the decoded charge array (MS:1000516), as written by Spectrum::deisotope
+    pub fn charge_array(&self) -> Option<&[f64]> {
+        find_array(&self.binary_data_arrays, accession::CHARGE_ARRAY)
+    }
+
+    /// Returns the decoded ion mobility array, whichever mobility or drift time term tags it
+    pub fn ion_mobility_array(&self) -> Option<&[f64]> {
+        ION_MOBILITY_ARRAYS
+            .iter()
+            .find_map(|accession| find_array(&self.binary_data_arrays, accession))
+    }
+
+    /// Iterates over (m/z, intensity) pairs, stopping at the shorter array
+    pub fn peaks(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        let mz = self.mz_array().unwrap_or_default();
+        let intensity = self.intensity_array().unwrap_or_default();
+        mz.iter().copied().zip(intensity.iter().copied())
+    }
+
+    /// Returns the number of (m/z, intensity) pairs
+    pub fn peak_count(&self) -> usize {
+        let mz = self.mz_array().map_or(0, <[f64]>::len);
+        let intensity = self.intensity_array().map_or(0, <[f64]>::len);
+        mz.min(intensity)
+    }
+
+    /// Returns the peaks with mz_min <= m/z <= mz_max, assuming the m/z array is sorted ascending
+    pub fn peaks_in_range(&self, mz_min: f64, mz_max: f64) -> Vec<(f64, f64)> {
+        let (Some(mz), Some(intensity)) = (self.mz_array(), self.intensity_array()) else {
+            return Vec::new();
+        };
+        let len = mz.len().min(intensity.len());
+        let mz = &mz[..len];
+
+        let start = mz.partition_point(|&v| v < mz_min);
+        let end = mz.partition_point(|&v| v <= mz_max).max(start);
+        mz[start..end]
+            .iter()
+            .copied()
+            .zip(intensity[start..end].iter().copied())
+            .collect()
+    }
+
+    /// Returns whether the m/z array is ascending, which peaks_in_range relies on
+    pub fn is_mz_sorted(&self) -> bool {
+        self.mz_array()
+            .is_none_or(|mz| mz.windows(2).all(|pair| pair[0] <= pair[1]))
+    }
+
+    /// Sorts the peaks by ascending m/z, reordering every decoded array of the same length with it
+    pub fn sort_by_mz(&mut self) {
+        if self.is_mz_sorted() {
+            return;
+        }
+        let Some(mz) = self.mz_array() else {
+            return;
+        };
+
+        // Step 1: find the order once, keeping equal m/z values in their original order
+        let mut order: Vec<usize> = (0..mz.len()).collect();
+        order.sort_by(|&a, &b| mz[a].total_cmp(&mz[b]));
+
+        // Step 2: apply it to the m/z array and every parallel array, such as intensity
+        let len = order.len();
+        for array in &mut self.binary_data_arrays {
+            let Some(values) = array.decoded_data.as_mut().filter(|v| v.len() == len) else {
+                continue;
+            };
+            *values = order.iter().map(|&i| values[i]).collect();
+            array.raw_binary = None;
+        }
+        self.clear_cache();
+    }
+
+    /// Sums peak intensities into fixed-width m/z bins from 0 up to mz_max
+    ///
+    /// This is the input peakpick::cosine_similarity expects. Peaks at or beyond mz_max are
+    /// dropped, and a non-positive bin_width or mz_max gives an empty vector.
+    pub fn binned(&self, bin_width: f32, mz_max: f32) -> Vec<f32> {
+        if !(bin_width > 0.0 && mz_max > 0.0) {
+            return Vec::new();
+        }
+        let mut bins = vec![0.0; (mz_max / bin_width).ceil() as usize];
+        for (mz, intensity) in self.peaks() {
+            if !(0.0..f64::from(mz_max)).contains(&mz) {
+                continue;
+            }
+            let bin = (mz / f64::from(bin_width)) as usize;
+            if let Some(total) = bins.get_mut(bin) {
+                *total += intensity as f32;
+            }
+        }
+        bins
+    }
+
+    /// Returns a copy with the decoded intensity array scaled by the given mode
+    ///
+    /// The m/z array and the stored TIC and base peak cvParams are left as they were. A
+    /// spectrum without intensities, or whose intensities are all zero, is returned unscaled.
+    pub fn normalize(&self, mode: NormalizationMode) -> Spectrum {
+        let mut normalized = self.clone();
+        let Some(array) = normalized
+            .binary_data_arrays
+            .iter_mut()
+            .find(|a| find_cv_param(&a.cv_params, accession::INTENSITY_ARRAY).is_some())
+        else {
+            return normalized;
+        };
+        let Some(intensities) = array.decoded_data.as_mut() else {
+            return normalized;
+        };
+
+        let divisor = match mode {
+            NormalizationMode::Tic => intensities.iter().sum(),
+            NormalizationMode::BasePeak => intensities.iter().copied().fold(0.0, f64::max),
+            NormalizationMode::Euclidean => intensities.iter().map(|i| i * i).sum::<f64>().sqrt(),
+        };
+        if divisor == 0.0 || !divisor.is_finite() {
+            return normalized;
+        }
+        intensities.iter_mut().for_each(|i| *i /= divisor);
+        array.raw_binary = None;
+        normalized
+    }
+
+    /// Returns the peaks as an N x 2 array of m/z and intensity columns
+    #[cfg(feature = "ndarray")]
+    pub fn mz_intensity_ndarray(&self) -> Option<ndarray::Array2<f64>> {
+        self.mz_array()?;
+        self.intensity_array()?;
+        let values: Vec<f64> = self.peaks().flat_map(|(mz, i)| [mz, i]).collect();
+        ndarray::Array2::from_shape_vec((values.len() / 2, 2), values).ok()
+    }
+
+    /// Returns the (m/z, intensity) pair with the highest intensity in the arrays
+    pub fn base_peak(&self) -> Option<(f64, f64)> {
+        self.peaks()
+            .fold(None, |best: Option<(f64, f64)>, peak| match best {
+                Some((_, intensity)) if intensity >= peak.1 => best,
+                _ => Some(peak),
+            })
+    }
+
+    /// Returns min, max, mean, median and sum of the intensity array, or None if it is absent or empty
+    ///
+    /// The result is cached, so later calls return it without touching the array. Nothing is
+    /// cached while the array is missing, e.g. before a lazy decode.
+    pub fn intensity_stats(&self) -> Option<IntensityStats> {
+        if let Some(stats) = self.cache.intensity_stats.get() {
+            return Some(*stats);
+        }
+        let stats = self.compute_intensity_stats()?;
+        Some(*self.cache.intensity_stats.get_or_init(|| stats))
+    }
+
+    /// Forgets the values cached from the arrays, e.g. after editing binary_data_arrays
+    pub fn clear_cache(&mut self) {
+        self.cache = SpectrumCache::default();
+    }
+
+    /// Computes intensity_stats from the array, bypassing the cache
+    fn compute_intensity_stats(&self) -> Option<IntensityStats> {
+        let intensities = self.intensity_array()?;
+        if intensities.is_empty() {
+            return None;
+        }
+
+        let mut sorted = intensities.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let middle = sorted.len() / 2;
+        let median = if sorted.len() % 2 == 0 {
+            (sorted[middle - 1] + sorted[middle]) / 2.0
+        } else {
+            sorted[middle]
+        };
+        let sum: f64 = sorted.iter().sum();
+
+        Some(IntensityStats {
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            mean: sum / sorted.len() as f64,
+            median,
+            sum,
+        })
+    }
+
+    /// Returns the selected ion m/z (MS:1000744) of the first precursor
+    pub fn precursor_mz(&self) -> Option<f64> {
+        parse_cv_value(self.first_selected_ion()?, accession::SELECTED_ION_MZ)
+    }
+
+    /// Returns the charge state (MS:1000041) of the first precursor
+    pub fn precursor_charge(&self) -> Option<i32> {
+        parse_cv_value(self.first_selected_ion()?, accession::CHARGE_STATE)
+    }
+
+    /// Returns the selected ion peak intensity (MS:1000042) of the first precursor
+    pub fn precursor_intensity(&self) -> Option<f64> {
+        parse_cv_value(self.first_selected_ion()?, accession::PEAK_INTENSITY)
+    }
+
+    /// Returns the isolation window of the first precursor
+    pub fn isolation_window(&self) -> Option<IsolationWindow> {
+        self.precursors.first()?.isolation_window_bounds()
+    }
+
+    /// Returns the total ion current (MS:1000285), summing the intensity array if it is absent
+    pub fn total_ion_current(&self) -> Option<f64> {
+        parse_cv_value(&self.cv_params, accession::TOTAL_ION_CURRENT)
+            .or_else(|| Some(self.intensity_array()?.iter().sum()))
+    }
+
+    /// Returns |stored - computed| / stored for the TIC cvParam against the summed intensity array
+    ///
+    /// None if either is missing. A stored TIC of 0 gives 0 if the array also sums to 0 and
+    /// infinity otherwise.
+    pub fn tic_consistency(&self) -> Option<f64> {
+        let stored: f64 = parse_cv_value(&self.cv_params, accession::TOTAL_ION_CURRENT)?;
+        let computed: f64 = self.intensity_array()?.iter().sum();
+        let difference = (stored - computed).abs();
+        if difference == 0.0 {
+            return Some(0.0);
+        }
+        Some(difference / stored.abs())
+    }
+
+    /// Returns the base peak m/z (MS:1000504)
+    pub fn base_peak_mz(&self) -> Option<f64> {
+        parse_cv_value(&self.cv_params, accession::BASE_PEAK_MZ)
+    }
+
+    /// Returns the base peak intensity (MS:1000505)
+    pub fn base_peak_intensity(&self) -> Option<f64> {
+        parse_cv_value(&self.cv_params, accession::BASE_PEAK_INTENSITY)
+    }
+
+    /// Returns the scan polarity (MS:1000130 positive, MS:1000129 negative)
+    pub fn polarity(&self) -> Option<Polarity> {
+        self.cv_params.iter().find_map(|p| match &*p.accession {
+            accession::POSITIVE_SCAN => Some(Polarity::Positive),
+            accession::NEGATIVE_SCAN => Some(Polarity::Negative),
+            _ => None,
+        })
+    }
+
+    /// Returns true if the spectrum is flagged as centroided (MS:1000127)
+    pub fn is_centroided(&self) -> bool {
+        find_cv_param(&self.cv_params, accession::CENTROID_SPECTRUM).is_some()
+    }
+
+    /// Returns true if the spectrum is flagged as profile (MS:1000128)
+    pub fn is_profile(&self) -> bool {
+        find_cv_param(&self.cv_params, accession::PROFILE_SPECTRUM).is_some()
+    }
+
+    /// Returns the scan number from the `scan=` token of the nativeID
+    pub fn scan_number(&self) -> Option<u64> {
+        self.id
+            .split_whitespace()
+            .find_map(|token| token.strip_prefix("scan="))?
+            .parse()
+            .ok()
+    }
+
+    /// Splits a nativeID such as `controllerType=0 controllerNumber=1 scan=1234` into key/value pairs
+    pub fn id_fields(&self) -> HashMap<String, String> {
+        self.id
+            .split_whitespace()
+            .filter_map(|token| token.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Checks that every decoded array holds defaultArrayLength values
+    pub fn validate(&self) -> Result<()> {
+        for (i, array) in self.binary_data_arrays.iter().enumerate() {
+            let Some(values) = array.decoded_data.as_deref() else {
+                continue;
+            };
+            if values.len() != self.default_array_length {
+                let kind = array
+                    .cv_params
+                    .iter()
+                    .find(|p| p.name.ends_with(" array"))
+                    .map_or_else(|| format!("binary array {}", i), |p| p.name.to_string());
+                return Err(MzmlError::ArrayLengthMismatch {
+                    spectrum_id: self.id.clone(),
+                    array: kind,
+                    actual: values.len(),
+                    expected: self.default_array_length,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn first_selected_ion(&self) -> Option<&[CvParam]> {
+        self.precursors
+            .first()?
+            .selected_ions
+            .first()
+            .map(Vec::as_slice)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Polarity {
+    Positive,
+    Negative,
+}
+
+/// How Spectrum::normalize scales intensities
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NormalizationMode {
+    /// Divide by the summed intensity, so the intensities sum to 1
+    Tic,
+    /// Divide by the highest intensity, so the base peak is 1
+    BasePeak,
+    /// Divide by the Euclidean norm, so the intensity vector has length 1
+    Euclidean,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Chromatogram {
+    pub id: String,
+    pub index: usize,
+    pub default_array_length: usize,
+    pub cv_params: Vec<CvParam>,
+    /// The selected precursor of an SRM/MRM transition, from <precursor>
+    pub precursors: Vec<Precursor>,
+    /// The monitored fragments of an SRM/MRM transition, from <productList>
+    pub products: Vec<Product>,
+    pub binary_data_arrays: Vec<BinaryDataArray>,
+}
+
+impl Chromatogram {
+    /// Returns the decoded time array (MS:1000595)
+    pub fn time_array(&self) -> Option<&[f64]> {
+        find_array(&self.binary_data_arrays, accession::TIME_ARRAY)
+    }
+
+    /// Returns the decoded intensity array (MS:1000515)
+    pub fn intensity_array(&self) -> Option<&[f64]> {
+        find_array(&self.binary_data_arrays, accession::INTENSITY_ARRAY)
+    }
+}
+
+/// A controlled vocabulary term; the term strings are interned and shared across a parse
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CvParam {
-    pub cv_ref: String,
-    pub accession: String,
+    pub cv_ref: Arc<str>,
+    pub accession: Arc<str>,
+    pub name: Arc<str>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub value: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub unit_name: Option<Arc<str>>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub unit_accession: Option<Arc<str>>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub unit_cv_ref: Option<Arc<str>>,
+}
+
+/// Units CvParam::value_in_unit converts between: (accession, quantity, size in base units)
+const UNIT_SCALES: [(&str, &str, f64); 8] = [
+    (accession::SECOND, "time", 1.0),
+    (accession::MILLISECOND, "time", 1e-3),
+    (accession::MICROSECOND, "time", 1e-6),
+    (accession::MINUTE, "time", 60.0),
+    (accession::HOUR, "time", 3600.0),
+    (accession::VOLT, "voltage", 1.0),
+    (accession::MILLIVOLT, "voltage", 1e-3),
+    (accession::KILOVOLT, "voltage", 1e3),
+];
+
+impl CvParam {
+    /// Parses the value as a number
+    pub fn value_as_f64(&self) -> Option<f64> {
+        self.value.as_deref()?.trim().parse().ok()
+    }
+
+    /// Returns the value converted to the unit with the given accession, e.g. minutes to seconds
+    ///
+    /// The value is returned as is when it is already in that unit. Otherwise both units must
+    /// be UO time or voltage units of the same quantity, or None is returned.
+    pub fn value_in_unit(&self, target_accession: &str) -> Option<f64> {
+        let value = self.value_as_f64()?;
+        let unit = self.unit_accession.as_deref()?;
+        if unit == target_accession {
+            return Some(value);
+        }
+        let scale = |accession: &str| UNIT_SCALES.iter().find(|(a, _, _)| *a == accession);
+        let (_, from_quantity, from_scale) = scale(unit)?;
+        let (_, to_quantity, to_scale) = scale(target_accession)?;
+        (from_quantity == to_quantity).then(|| value * from_scale / to_scale)
+    }
+}
+
+/// A borrowed view of a <cvParam> whose strings point into the source document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CvParamRef<'a> {
+    pub cv_ref: &'a str,
+    pub accession: &'a str,
+    pub name: &'a str,
+    pub value: Option<&'a str>,
+    pub unit_name: Option<&'a str>,
+    pub unit_accession: Option<&'a str>,
+    pub unit_cv_ref: Option<&'a str>,
+}
+
+impl CvParamRef<'_> {
+    /// Copies the view into an owned CvParam
+    pub fn to_cv_param(&self) -> CvParam {
+        CvParam {
+            cv_ref: self.cv_ref.into(),
+            accession: self.accession.into(),
+            name: self.name.into(),
+            value: self.value.map(str::to_string),
+            unit_name: self.unit_name.map(Into::into),
+            unit_accession: self.unit_accession.map(Into::into),
+            unit_cv_ref: self.unit_cv_ref.map(Into::into),
+        }
+    }
+}
+
+/// A free-form <userParam>, typically holding vendor-specific metadata
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UserParam {
     pub name: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub value: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub type_: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub unit_name: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub unit_accession: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
     pub unit_cv_ref: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ScanList {
     pub count: usize,
     pub cv_params: Vec<CvParam>,
     pub scans: Vec<Scan>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Scan {
+    /// The instrumentConfigurationRef attribute, naming the configuration that acquired the scan
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub instrument_configuration_ref: Option<String>,
     pub cv_params: Vec<CvParam>,
+    pub user_params: Vec<UserParam>,
     pub scan_windows: Vec<ScanWindow>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ScanWindow {
     pub cv_params: Vec<CvParam>,
 }
 
-#[derive(Debug, Clone)]
+impl Scan {
+    /// Returns the (lower, upper) m/z limits of each scan window, skipping windows missing either
+    pub fn mz_ranges(&self) -> Vec<(f64, f64)> {
+        self.scan_windows
+            .iter()
+            .filter_map(|window| {
+                let lower = parse_cv_value(&window.cv_params, accession::SCAN_WINDOW_LOWER_LIMIT)?;
+                let upper = parse_cv_value(&window.cv_params, accession::SCAN_WINDOW_UPPER_LIMIT)?;
+                Some((lower, upper))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Precursor {
+    pub spectrum_ref: Option<String>,
+    pub isolation_window: Vec<CvParam>,
+    pub selected_ions: Vec<Vec<CvParam>>,
+    pub activation: Activation,
+}
+
+impl Precursor {
+    /// Returns the typed isolation window, or None if it has none of the target/offset params
+    pub fn isolation_window_bounds(&self) -> Option<IsolationWindow> {
+        IsolationWindow::from_cv_params(&self.isolation_window)
+    }
+}
+
+/// A fragment selected for monitoring, e.g. the product ion of an SRM transition
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Product {
+    pub isolation_window: IsolationWindow,
+}
+
+/// Accessions of the array terms recognised by Spectrum::ion_mobility_array
+const ION_MOBILITY_ARRAYS: [&str; 10] = [
+    accession::MEAN_INVERSE_REDUCED_ION_MOBILITY_ARRAY,
+    accession::RAW_INVERSE_REDUCED_ION_MOBILITY_ARRAY,
+    accession::DECONVOLUTED_INVERSE_REDUCED_ION_MOBILITY_ARRAY,
+    accession::MEAN_DRIFT_TIME_ARRAY,
+    accession::RAW_DRIFT_TIME_ARRAY,
+    accession::DECONVOLUTED_DRIFT_TIME_ARRAY,
+    accession::MEAN_ION_MOBILITY_ARRAY,
+    accession::RAW_ION_MOBILITY_ARRAY,
+    accession::DECONVOLUTED_ION_MOBILITY_ARRAY,
+    accession::ION_MOBILITY_ARRAY,
+];
+
+/// Accessions of the dissociation method terms recognised by Activation::method
+const DISSOCIATION_METHODS: [&str; 12] = [
+    accession::CID,
+    accession::PLASMA_DESORPTION,
+    accession::POST_SOURCE_DECAY,
+    accession::SURFACE_INDUCED_DISSOCIATION,
+    accession::BIRD,
+    accession::ECD,
+    accession::IRMPD,
+    accession::SORI,
+    accession::HCD,
+    accession::PHOTODISSOCIATION,
+    accession::ETD,
+    accession::PQD,
+];
+
+/// How a precursor was fragmented, as recorded in <activation>
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Activation {
+    pub cv_params: Vec<CvParam>,
+}
+
+impl Activation {
+    /// Returns the name of the first dissociation method term, e.g. "collision-induced dissociation"
+    pub fn method(&self) -> Option<&str> {
+        self.cv_params
+            .iter()
+            .find(|p| DISSOCIATION_METHODS.contains(&&*p.accession))
+            .map(|p| &*p.name)
+    }
+
+    /// Returns the collision energy (MS:1000045)
+    pub fn collision_energy(&self) -> Option<f64> {
+        parse_cv_value(&self.cv_params, accession::COLLISION_ENERGY)
+    }
+}
+
+/// Summary statistics over a spectrum's intensity array
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IntensityStats {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub sum: f64,
+}
+
+/// The m/z window isolated for fragmentation, as a target and offsets either side of it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IsolationWindow {
+    pub target_mz: Option<f64>,
+    pub lower_offset: Option<f64>,
+    pub upper_offset: Option<f64>,
+}
+
+impl IsolationWindow {
+    /// Reads the target and offset params of an <isolationWindow>, or None if it has none of them
+    pub fn from_cv_params(cv_params: &[CvParam]) -> Option<IsolationWindow> {
+        let window = IsolationWindow {
+            target_mz: parse_cv_value(cv_params, accession::ISOLATION_WINDOW_TARGET_MZ),
+            lower_offset: parse_cv_value(cv_params, accession::ISOLATION_WINDOW_LOWER_OFFSET),
+            upper_offset: parse_cv_value(cv_params, accession::ISOLATION_WINDOW_UPPER_OFFSET),
+        };
+        if window.target_mz.is_none()
+            && window.lower_offset.is_none()
+            && window.upper_offset.is_none()
+        {
+            return None;
+        }
+        Some(window)
+    }
+
+    /// Returns the lowest isolated m/z (target - lower offset)
+    pub fn lower_bound(&self) -> Option<f64> {
+        Some(self.target_mz? - self.lower_offset?)
+    }
+
+    /// Returns the highest isolated m/z (target + upper offset)
+    pub fn upper_bound(&self) -> Option<f64> {
+        Some(self.target_mz? + self.upper_offset?)
+    }
+}
+
+/// Accessions of the compression terms reported by BinaryDataArray::codec
+const COMPRESSION_ACCESSIONS: [&str; 7] = [
+    accession::ZLIB_COMPRESSION,
+    accession::NUMPRESS_LINEAR,
+    accession::NUMPRESS_PIC,
+    accession::NUMPRESS_SLOF,
+    accession::NUMPRESS_LINEAR_ZLIB,
+    accession::NUMPRESS_PIC_ZLIB,
+    accession::NUMPRESS_SLOF_ZLIB,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BinaryDataArray {
     pub encoded_length: usize,
     pub cv_params: Vec<CvParam>,
-    pub decoded_data: Option<Vec<f32>>,
+    pub encoded_data: Option<String>,
+    pub decoded_data: Option<Vec<f64>>,
+    /// The <binary> text as read, kept if ParseOptions::keep_raw_binary is set so the writer
+    /// can emit it verbatim; clear it after changing decoded_data
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub raw_binary: Option<String>,
+}
+
+impl BinaryDataArray {
+    /// Decodes any pending encoded data using the array's compression and precision params
+    pub fn decode(&mut self) -> Result<()> {
+        let Some(encoded_data) = self.encoded_data.take() else {
+            return Ok(());
+        };
+
+        let codecs = self.codecs()?;
+
+        let precision = self.precision();
+
+        self.decoded_data = Some(decode_binary_data_with_codecs(
+            &encoded_data,
+            &codecs,
+            precision.unwrap_or(Precision::Float32),
+            ByteOrder::LittleEndian,
+        )?);
+        Ok(())
+    }
+
+    /// Returns decoded byte size / encodedLength, or None before decoding or without a length
+    pub fn compression_ratio(&self) -> Option<f64> {
+        let values = self.decoded_data.as_ref()?.len();
+        if self.encoded_length == 0 {
+            return None;
+        }
+        let value_size = self.precision().unwrap_or(Precision::Float32).size();
+        Some((values * value_size) as f64 / self.encoded_length as f64)
+    }
+
+    /// Returns the name of the compression cvParam, or None if the data is uncompressed
+    pub fn codec(&self) -> Option<&str> {
+        self.cv_params
+            .iter()
+            .find(|p| COMPRESSION_ACCESSIONS.contains(&&*p.accession))
+            .map(|p| &*p.name)
+    }
+
+    /// Returns the precision named by the array's cvParams
+    pub fn precision(&self) -> Option<Precision> {
+        self.cv_params
+            .iter()
+            .find_map(|p| Precision::from_accession(&p.accession))
+    }
+
+    /// Returns the codecs to undo, in order: zlib before any Numpress decode
+    ///
+    /// A cvParam named like a compression term that libms does not know is an error, rather
+    /// than being read as uncompressed data.
+    pub fn codecs(&self) -> Result<Vec<Codec>> {
+        let mut zlib = false;
+        let mut numpress = None;
+        for param in &self.cv_params {
+            match Codec::from_accession(&param.accession) {
+                Some(codecs) => {
+                    for &codec in codecs {
+                        match codec {
+                            Codec::Zlib => zlib = true,
+                            _ => numpress = Some(codec),
+                        }
+                    }
+                }
+                None if param.name.ends_with("compression") => {
+                    return Err(MzmlError::UnknownCompression(format!(
+                        "{} ({})",
+                        param.name, param.accession
+                    )))
+                }
+                None => {}
+            }
+        }
+        Ok(zlib
+            .then_some(Codec::Zlib)
+            .into_iter()
+            .chain(numpress)
+            .collect())
+    }
+}
+
+/// Finds the first cvParam with the given accession
+pub(crate) fn find_cv_param<'a>(params: &'a [CvParam], accession: &str) -> Option<&'a CvParam> {
+    params.iter().find(|p| &*p.accession == accession)
+}
+
+/// Returns the decoded data of the array tagged with the given accession
+fn find_array<'a>(arrays: &'a [BinaryDataArray], accession: &str) -> Option<&'a [f64]> {
+    arrays
+        .iter()
+        .find(|a| find_cv_param(&a.cv_params, accession).is_some())?
+        .decoded_data
+        .as_deref()
+}
+
+/// Parses the value of the first cvParam with the given accession
+fn parse_cv_value<T: FromStr>(params: &[CvParam], accession: &str) -> Option<T> {
+    find_cv_param(params, accession)?
+        .value
+        .as_deref()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CvParam, IntensityStats, NormalizationMode, Run, Spectrum};
+    use crate::accession;
+    use crate::error::MzmlError;
+    use crate::test_fixtures::{
+        binary_array, cv_param, cv_param_with_unit, f64_base64, mzml_with_spectra, spectrum, zlib,
+    };
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    #[test]
+    fn retention_time_converts_minutes_to_seconds() {
+        let scan_list = format!(
+            r#"<scanList count="1"><scan>{}</scan></scanList>"#,
+            cv_param_with_unit(accession::SCAN_START_TIME, "0.5", accession::MINUTE)
+        );
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[], &scan_list)]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        assert_eq!(run.spectra[0].retention_time(), Some(30.0));
+    }
+
+    #[test]
+    fn arrays_have_default_array_length_values() {
+        let peaks = [(100.0, 1.0), (200.0, 2.0), (300.0, 3.0)];
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &peaks, "")]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let spectrum = &run.spectra[0];
+        assert_eq!(spectrum.default_array_length, 3);
+        assert_eq!(spectrum.mz_array(), Some(&[100.0, 200.0, 300.0][..]));
+        assert_eq!(spectrum.intensity_array(), Some(&[1.0, 2.0, 3.0][..]));
+    }
+
+    #[test]
+    fn no_compression_param_passes_bytes_through() {
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 5.0)], "")]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let array = &run.spectra[0].binary_data_arrays[0];
+        assert!(array
+            .cv_params
+            .iter()
+            .any(|p| &*p.accession == accession::NO_COMPRESSION));
+        assert_eq!(array.codec(), None);
+        assert!(array.codecs().unwrap().is_empty());
+        assert_eq!(array.decoded_data.as_deref(), Some(&[100.0][..]));
+    }
+
+    #[test]
+    fn tic_and_base_peak_read_their_cv_params() {
+        let params = [
+            cv_param(accession::TOTAL_ION_CURRENT, "1000"),
+            cv_param(accession::BASE_PEAK_MZ, "445.12"),
+            cv_param(accession::BASE_PEAK_INTENSITY, "600"),
+        ];
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 5.0)], &params.concat())]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let spectrum = &run.spectra[0];
+        assert_eq!(spectrum.total_ion_current(), Some(1000.0));
+        assert_eq!(spectrum.base_peak_mz(), Some(445.12));
+        assert_eq!(spectrum.base_peak_intensity(), Some(600.0));
+    }
+
+    #[test]
+    fn tic_falls_back_to_the_intensity_sum_without_its_cv_param() {
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 5.0), (200.0, 7.0)], "")]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let spectrum = &run.spectra[0];
+        assert_eq!(spectrum.total_ion_current(), Some(12.0));
+        assert_eq!(spectrum.base_peak_mz(), None);
+        assert_eq!(spectrum.base_peak_intensity(), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn run_round_trips_through_json() {
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 5.0)], ""),
+            spectrum(1, 2, &[(50.0, 1.0), (60.0, 2.0)], ""),
+        ]);
+        let run = crate::parse_mzml(&xml).unwrap();
+
+        let json = serde_json::to_string(&run).unwrap();
+        let back: Run = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, run);
+    }
+
+    fn run_of_two() -> Run {
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 5.0)], ""),
+            spectrum(1, 2, &[(50.0, 1.0)], ""),
+        ]);
+        crate::parse_mzml(&xml).unwrap()
+    }
+
+    #[test]
+    fn spectra_are_found_by_id_and_index() {
+        let run = run_of_two();
+        assert_eq!(run.get_spectrum_by_id("scan=2").unwrap().index, 1);
+        assert_eq!(run.get_spectrum_by_index(0).unwrap().id, "scan=1");
+    }
+
+    #[test]
+    fn missing_ids_and_indices_give_none() {
+        let run = run_of_two();
+        assert!(run.get_spectrum_by_id("scan=3").is_none());
+        assert!(run.get_spectrum_by_index(2).is_none());
+    }
+
+    #[test]
+    fn peaks_pairs_mz_with_intensity() {
+        let spectrum = Spectrum::builder()
+            .peaks(vec![100.0, 200.0, 300.0], vec![1.0, 2.0, 3.0])
+            .build();
+
+        let peaks: Vec<_> = spectrum.peaks().collect();
+        assert_eq!(peaks, [(100.0, 1.0), (200.0, 2.0), (300.0, 3.0)]);
+        assert_eq!(spectrum.peak_count(), 3);
+    }
+
+    fn four_peaks() -> Spectrum {
+        Spectrum::builder()
+            .peaks(vec![100.0, 200.0, 300.0, 400.0], vec![1.0, 2.0, 3.0, 4.0])
+            .build()
+    }
+
+    #[test]
+    fn peaks_in_range_keeps_a_mid_spectrum_window() {
+        let spectrum = four_peaks();
+        assert_eq!(
+            spectrum.peaks_in_range(150.0, 300.0),
+            [(200.0, 2.0), (300.0, 3.0)]
+        );
+    }
+
+    #[test]
+    fn peaks_in_range_is_empty_outside_the_peaks() {
+        let spectrum = four_peaks();
+        assert!(spectrum.peaks_in_range(210.0, 290.0).is_empty());
+        assert!(spectrum.peaks_in_range(500.0, 600.0).is_empty());
+        assert!(spectrum.peaks_in_range(300.0, 200.0).is_empty());
+    }
+
+    #[test]
+    fn validate_rejects_a_short_array() {
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 1.0), (200.0, 2.0)], "")])
+            .replace(r#"defaultArrayLength="2""#, r#"defaultArrayLength="3""#);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        match run.spectra[0].validate() {
+            Err(MzmlError::ArrayLengthMismatch {
+                array,
+                actual,
+                expected,
+                ..
+            }) => {
+                assert_eq!(array, "m/z array");
+                assert_eq!((actual, expected), (2, 3));
+            }
+            other => panic!("expected an array length mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decodes_zlib_then_numpress_linear() {
+        let mz = [100.0, 100.5, 250.25, 999.875];
+        let numpress = numpress::numpress_compress(&mz, 10000.0).unwrap();
+        let array = binary_array(
+            &[accession::MZ_ARRAY, accession::NUMPRESS_LINEAR_ZLIB],
+            &STANDARD.encode(zlib(&numpress)),
+        );
+        let xml = mzml_with_spectra(&[format!(
+            r#"<spectrum index="0" id="scan=1" defaultArrayLength="4"><binaryDataArrayList count="1">{array}</binaryDataArrayList></spectrum>"#
+        )]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let decoded = run.spectra[0].mz_array().unwrap();
+        assert_eq!(decoded.len(), mz.len());
+        for (decoded, expected) in decoded.iter().zip(mz) {
+            assert!((decoded - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn zlib_array_has_a_compression_ratio_above_one() {
+        let bytes: Vec<u8> = [1.0f64; 100].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let array = binary_array(
+            &[
+                accession::INTENSITY_ARRAY,
+                accession::FLOAT_64_BIT,
+                accession::ZLIB_COMPRESSION,
+            ],
+            &STANDARD.encode(zlib(&bytes)),
+        );
+        let xml = mzml_with_spectra(&[format!(
+            r#"<spectrum index="0" id="scan=1" defaultArrayLength="100"><binaryDataArrayList count="1">{array}</binaryDataArrayList></spectrum>"#
+        )]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let array = &run.spectra[0].binary_data_arrays[0];
+        assert_eq!(array.codec(), Some("zlib compression"));
+        assert!(array.compression_ratio().unwrap() > 1.0);
+    }
+
+    #[test]
+    fn thermo_ids_split_into_fields() {
+        let spectrum = Spectrum::builder()
+            .id("controllerType=0 controllerNumber=1 scan=1234")
+            .build();
+
+        assert_eq!(spectrum.scan_number(), Some(1234));
+        let fields = spectrum.id_fields();
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields["controllerType"], "0");
+        assert_eq!(fields["controllerNumber"], "1");
+    }
+
+    #[test]
+    fn generic_ids_have_no_scan_number() {
+        let spectrum = Spectrum::builder().id("index=7").build();
+        assert_eq!(spectrum.scan_number(), None);
+        assert_eq!(spectrum.id_fields()["index"], "7");
+
+        let spectrum = Spectrum::builder().id("spectrum seven").build();
+        assert_eq!(spectrum.scan_number(), None);
+        assert!(spectrum.id_fields().is_empty());
+    }
+
+    #[test]
+    fn spectra_are_grouped_by_ms_level() {
+        let run = Run::builder()
+            .spectra([1, 2, 2, 1, 2].map(|level| Spectrum::builder().ms_level(level).build()))
+            .build();
+
+        assert_eq!(run.spectra_by_ms_level(2).count(), 3);
+        assert_eq!(run.spectra_by_ms_level(3).count(), 0);
+        let counts = run.ms_level_counts();
+        assert_eq!(counts.get(&1), Some(&2));
+        assert_eq!(counts.get(&2), Some(&3));
+    }
+
+    #[test]
+    fn intensity_stats_average_the_middle_pair_of_an_even_array() {
+        let spectrum = Spectrum::builder()
+            .peaks(vec![1.0, 2.0, 3.0, 4.0], vec![8.0, 2.0, 4.0, 10.0])
+            .build();
+
+        assert_eq!(
+            spectrum.intensity_stats(),
+            Some(IntensityStats {
+                min: 2.0,
+                max: 10.0,
+                mean: 6.0,
+                median: 6.0,
+                sum: 24.0,
+            })
+        );
+        assert_eq!(Spectrum::builder().build().intensity_stats(), None);
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn ndarray_has_one_row_per_peak() {
+        let xml = mzml_with_spectra(&[spectrum(
+            0,
+            1,
+            &[(100.0, 1.0), (200.0, 5.0), (300.0, 2.5)],
+            "",
+        )]);
+        let run = crate::parse_mzml(&xml).unwrap();
+
+        let peaks = run.spectra[0].mz_intensity_ndarray().unwrap();
+        assert_eq!(peaks.shape(), &[3, 2]);
+        assert_eq!(peaks[[0, 0]], 100.0);
+        assert_eq!(peaks[[1, 1]], 5.0);
+        assert_eq!(peaks[[2, 0]], 300.0);
+
+        let mut no_intensity = run.spectra[0].clone();
+        no_intensity.binary_data_arrays.truncate(1);
+        assert!(no_intensity.mz_intensity_ndarray().is_none());
+    }
+
+    #[cfg(feature = "polars")]
+    #[test]
+    fn summary_dataframe_has_a_row_per_spectrum() {
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 1.0), (200.0, 5.0)], ""),
+            spectrum(1, 2, &[(150.0, 2.0)], ""),
+            spectrum(2, 1, &[], ""),
+        ]);
+        let run = crate::parse_mzml(&xml).unwrap();
+
+        let frame = run.to_summary_dataframe().unwrap();
+        assert_eq!(frame.height(), 3);
+        let names: Vec<&str> = frame
+            .get_column_names()
+            .iter()
+            .map(|n| n.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "index",
+                "id",
+                "ms_level",
+                "retention_time",
+                "tic",
+                "base_peak_mz",
+                "precursor_mz",
+                "num_peaks"
+            ]
+        );
+    }
+
+    #[test]
+    fn ion_mobility_array_is_found_by_accession() {
+        let arrays = [
+            accession::MZ_ARRAY,
+            accession::INTENSITY_ARRAY,
+            accession::MEAN_INVERSE_REDUCED_ION_MOBILITY_ARRAY,
+        ]
+        .map(|term| {
+            binary_array(
+                &[term, accession::FLOAT_64_BIT, accession::NO_COMPRESSION],
+                &f64_base64(&[0.8, 1.1]),
+            )
+        });
+        let xml = mzml_with_spectra(&[format!(
+            r#"<spectrum index="0" id="scan=1" defaultArrayLength="2"><binaryDataArrayList count="3">{}</binaryDataArrayList></spectrum>"#,
+            arrays.concat()
+        )]);
+        let run = crate::parse_mzml(&xml).unwrap();
+        assert_eq!(run.spectra[0].ion_mobility_array(), Some(&[0.8, 1.1][..]));
+
+        let plain = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 1.0)], "")]);
+        let run = crate::parse_mzml(&plain).unwrap();
+        assert_eq!(run.spectra[0].ion_mobility_array(), None);
+    }
+
+    /// Returns an MS1 spectrum with the given id, retention time and peaks
+    fn ms1_at(id: &str, seconds: Option<f64>, mz: Vec<f64>, intensity: Vec<f64>) -> Spectrum {
+        let builder = Spectrum::builder().id(id).ms_level(1).peaks(mz, intensity);
+        match seconds {
+            Some(seconds) => builder.retention_time(seconds).build(),
+            None => builder.build(),
+        }
+    }
+
+    #[test]
+    fn out_of_order_run_sorts_by_retention_time() {
+        let mut run = Run::builder()
+            .spectra([
+                ms1_at("c", Some(30.0), vec![], vec![]),
+                ms1_at("none1", None, vec![], vec![]),
+                ms1_at("a", Some(10.0), vec![], vec![]),
+                ms1_at("b", Some(20.0), vec![], vec![]),
+                ms1_at("a2", Some(10.0), vec![], vec![]),
+                ms1_at("none2", None, vec![], vec![]),
+            ])
+            .build();
+        assert_eq!(run.retention_time_range(), Some((10.0, 30.0)));
+
+        run.sort_by_retention_time();
+        let ids: Vec<&str> = run.spectra.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["a", "a2", "b", "c", "none1", "none2"]);
+        assert!(Run::builder().build().retention_time_range().is_none());
+    }
+
+    #[test]
+    fn xic_follows_the_elution_profile() {
+        // 500.0 elutes as 10, 40, 100, 40, 10; 500.02 is 40 ppm away and 501.0 far outside
+        let profile = [10.0, 40.0, 100.0, 40.0, 10.0];
+        let mut spectra: Vec<Spectrum> = profile
+            .iter()
+            .enumerate()
+            .map(|(i, &height)| {
+                ms1_at(
+                    &format!("ms1-{i}"),
+                    Some(i as f64 * 6.0),
+                    vec![499.999, 500.001, 500.02, 501.0],
+                    vec![height / 2.0, height / 2.0, 7.0, 1000.0],
+                )
+            })
+            .collect();
+        spectra.insert(
+            3,
+            Spectrum::builder()
+                .ms_level(2)
+                .retention_time(13.0)
+                .peaks(vec![500.0], vec![5000.0])
+                .build(),
+        );
+        let run = Run::builder().spectra(spectra).build();
+
+        let xic = run.extract_ion_chromatogram(500.0, 10.0);
+        let expected: Vec<(f64, f64)> = profile
+            .iter()
+            .enumerate()
+            .map(|(i, &height)| (i as f64 * 6.0, height))
+            .collect();
+        assert_eq!(xic, expected);
+    }
+
+    #[test]
+    fn tic_and_bpc_agree_with_their_cv_params() {
+        let peaks = [
+            [(100.0, 1.5), (200.0, 2.25)],
+            [(100.0, 4.0), (300.0, 0.5)],
+            [(150.0, 3.0), (250.0, 3.5)],
+        ];
+        let spectra = |with_params: bool| -> Vec<String> {
+            peaks
+                .iter()
+                .enumerate()
+                .map(|(i, peaks)| {
+                    let scan_list = format!(
+                        r#"<scanList count="1"><scan>{}</scan></scanList>"#,
+                        cv_param_with_unit(
+                            accession::SCAN_START_TIME,
+                            &(i * 5).to_string(),
+                            accession::SECOND
+                        )
+                    );
+                    // Converters write the summaries as 32-bit floats, so they are close, not equal
+                    let tic: f64 = peaks.iter().map(|&(_, intensity)| intensity).sum();
+                    let base = peaks.iter().map(|&(_, i)| i).fold(0.0, f64::max);
+                    let params = if with_params {
+                        cv_param(
+                            accession::TOTAL_ION_CURRENT,
+                            &(tic as f32 + 1e-6).to_string(),
+                        ) + &cv_param(accession::BASE_PEAK_INTENSITY, &(base as f32).to_string())
+                    } else {
+                        String::new()
+                    };
+                    spectrum(i, 1, peaks, &(params + &scan_list))
+                })
+                .collect()
+        };
+        let from_params = crate::parse_mzml(&mzml_with_spectra(&spectra(true))).unwrap();
+        let computed = crate::parse_mzml(&mzml_with_spectra(&spectra(false))).unwrap();
+        assert!(computed.spectra[0].cv_params.len() < from_params.spectra[0].cv_params.len());
+
+        for (reported, computed) in [
+            (
+                from_params.total_ion_chromatogram(),
+                computed.total_ion_chromatogram(),
+            ),
+            (
+                from_params.base_peak_chromatogram(),
+                computed.base_peak_chromatogram(),
+            ),
+        ] {
+            assert_eq!(reported.len(), 3);
+            assert_eq!(reported.len(), computed.len());
+            for ((rt_a, a), (rt_b, b)) in reported.iter().zip(&computed) {
+                assert_eq!(rt_a, rt_b);
+                assert!((a - b).abs() < 1e-4, "{a} vs {b}");
+            }
+        }
+        assert_ne!(from_params.total_ion_chromatogram()[1].1, 4.5);
+        assert_eq!(computed.total_ion_chromatogram()[1], (5.0, 4.5));
+        assert_eq!(computed.base_peak_chromatogram()[2], (10.0, 3.5));
+    }
+
+    #[test]
+    fn slices_past_the_end_are_clamped() {
+        let run = run_of_two();
+        let ids = |spectra: Vec<&Spectrum>| -> Vec<String> {
+            spectra.iter().map(|s| s.id.clone()).collect()
+        };
+
+        assert_eq!(ids(run.slice_spectra(1..10)), ["scan=2"]);
+        assert_eq!(ids(run.slice_spectra(0..2)), ["scan=1", "scan=2"]);
+        assert!(run.slice_spectra(5..8).is_empty());
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = run.slice_spectra(2..1);
+        assert!(reversed.is_empty());
+        assert_eq!(ids(run.first_n(1)), ["scan=1"]);
+        assert_eq!(run.first_n(100).len(), 2);
+    }
+
+    #[test]
+    fn tic_consistency_reports_the_relative_difference() {
+        // The arrays sum to 90 against a stored TIC of 100: a 10% discrepancy
+        let peaks = [(100.0, 30.0), (200.0, 60.0)];
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &peaks, &cv_param(accession::TOTAL_ION_CURRENT, "100")),
+            spectrum(1, 1, &peaks, &cv_param(accession::TOTAL_ION_CURRENT, "90")),
+            spectrum(2, 1, &peaks, ""),
+            spectrum(3, 1, &peaks, &cv_param(accession::TOTAL_ION_CURRENT, "0")),
+        ]);
+        let run = crate::parse_mzml(&xml).unwrap();
+
+        let consistency: Vec<Option<f64>> =
+            run.spectra.iter().map(Spectrum::tic_consistency).collect();
+        assert!((consistency[0].unwrap() - 0.1).abs() < 1e-12);
+        assert_eq!(consistency[1], Some(0.0));
+        assert_eq!(consistency[2], None);
+        assert_eq!(consistency[3], Some(f64::INFINITY));
+    }
+
+    #[test]
+    fn spectrum_at_rt_picks_the_nearest_ms1_scan() {
+        let ms2 = Spectrum::builder()
+            .id("ms2")
+            .ms_level(2)
+            .retention_time(738.0)
+            .build();
+        let run = Run::builder()
+            .spectra([
+                ms1_at("a", Some(730.0), vec![], vec![]),
+                ms2,
+                ms1_at("b", Some(740.0), vec![], vec![]),
+                ms1_at("c", Some(750.0), vec![], vec![]),
+            ])
+            .build();
+
+        // 12.3 minutes; the MS2 scan at 738 s is closer but not MS1
+        assert_eq!(run.spectrum_at_rt(738.0).unwrap().id, "b");
+        assert_eq!(run.spectrum_at_rt(733.0).unwrap().id, "a");
+        assert_eq!(run.spectrum_at_rt(745.0).unwrap().id, "b");
+        assert_eq!(run.spectrum_at_rt(9999.0).unwrap().id, "c");
+        let ids: Vec<&str> = run
+            .spectra_in_rt_range(735.0, 745.0)
+            .iter()
+            .map(|s| s.id.as_str())
+            .collect();
+        assert_eq!(ids, ["ms2", "b"]);
+        assert!(Run::builder().build().spectrum_at_rt(1.0).is_none());
+    }
+
+    #[test]
+    fn instrument_model_and_serial_come_from_the_configuration() {
+        let header = format!(
+            r#"<instrumentConfigurationList count="1"><instrumentConfiguration id="IC1">{}{}<componentList count="1"><analyzer order="1">{}</analyzer></componentList></instrumentConfiguration></instrumentConfigurationList>"#,
+            cv_param(
+                accession::INSTRUMENT_SERIAL_NUMBER,
+                "Exactive Series slot #1"
+            ),
+            cv_param(accession::Q_EXACTIVE, ""),
+            r#"<cvParam cvRef="MS" accession="MS:1000484" name="orbitrap" value=""/>"#
+        );
+        let xml = crate::test_fixtures::mzml_with_header(&header, "");
+
+        let document = crate::parse_mzml_document(&xml).unwrap();
+        assert_eq!(document.instrument_model(), Some("Q Exactive"));
+        assert_eq!(
+            document.instrument_serial_number(),
+            Some("Exactive Series slot #1")
+        );
+
+        let generic = crate::test_fixtures::mzml_with_header(
+            &header.replace(
+                &cv_param(accession::Q_EXACTIVE, ""),
+                &cv_param(accession::INSTRUMENT_MODEL, "Custom QTOF"),
+            ),
+            "",
+        );
+        let document = crate::parse_mzml_document(&generic).unwrap();
+        assert_eq!(document.instrument_model(), Some("Custom QTOF"));
+    }
+
+    #[test]
+    fn sort_by_mz_reorders_an_unsorted_spectrum() {
+        let mut spectrum = Spectrum::builder()
+            .peaks(vec![300.0, 100.0, 200.0, 100.0], vec![3.0, 1.0, 2.0, 4.0])
+            .build();
+        assert!(!spectrum.is_mz_sorted());
+        // The binary search gives wrong results while the array is out of order
+        assert_ne!(spectrum.peaks_in_range(250.0, 350.0), [(300.0, 3.0)]);
+
+        spectrum.sort_by_mz();
+        assert!(spectrum.is_mz_sorted());
+        assert_eq!(spectrum.mz_array(), Some(&[100.0, 100.0, 200.0, 300.0][..]));
+        assert_eq!(spectrum.intensity_array(), Some(&[1.0, 4.0, 2.0, 3.0][..]));
+        assert_eq!(spectrum.peaks_in_range(250.0, 350.0), [(300.0, 3.0)]);
+        assert_eq!(
+            spectrum.peaks_in_range(90.0, 110.0),
+            [(100.0, 1.0), (100.0, 4.0)]
+        );
+        assert!(four_peaks().is_mz_sorted());
+    }
+
+    #[test]
+    fn filter_string_is_read_from_the_scan() {
+        let filter = "FTMS + p NSI Full ms [350.0000-1800.0000]";
+        let scan_list =
+            |params: &str| format!(r#"<scanList count="1"><scan>{params}</scan></scanList>"#);
+        let xml = mzml_with_spectra(&[
+            spectrum(
+                0,
+                1,
+                &[],
+                &scan_list(&cv_param(accession::FILTER_STRING, filter)),
+            ),
+            spectrum(
+                1,
+                1,
+                &[],
+                &scan_list(&format!(
+                    r#"<userParam name="filter string" value="{filter}"/>"#
+                )),
+            ),
+            spectrum(2, 1, &[], &scan_list("")),
+        ]);
+        let run = crate::parse_mzml(&xml).unwrap();
+
+        assert_eq!(run.spectra[0].filter_string(), Some(filter));
+        assert_eq!(run.spectra[1].filter_string(), Some(filter));
+        assert_eq!(run.spectra[2].filter_string(), None);
+    }
+
+    #[test]
+    fn base_peak_normalization_scales_the_max_to_one() {
+        let spectrum = four_peaks();
+
+        let normalized = spectrum.normalize(NormalizationMode::BasePeak);
+        let intensity = normalized.intensity_array().unwrap();
+        assert_eq!(intensity.iter().copied().fold(f64::MIN, f64::max), 1.0);
+        assert_eq!(intensity, [0.25, 0.5, 0.75, 1.0]);
+        assert_eq!(normalized.mz_array(), spectrum.mz_array());
+        // The original is untouched
+        assert_eq!(spectrum.intensity_array(), Some(&[1.0, 2.0, 3.0, 4.0][..]));
+    }
+
+    #[test]
+    fn tic_and_euclidean_normalization_scale_to_unit_totals() {
+        let spectrum = four_peaks();
+
+        let tic = spectrum.normalize(NormalizationMode::Tic);
+        let total: f64 = tic.intensity_array().unwrap().iter().sum();
+        assert!((total - 1.0).abs() < 1e-12);
+        let euclidean = spectrum.normalize(NormalizationMode::Euclidean);
+        let norm: f64 = euclidean
+            .intensity_array()
+            .unwrap()
+            .iter()
+            .map(|v| v * v)
+            .sum();
+        assert!((norm - 1.0).abs() < 1e-12);
+
+        let silent = Spectrum::builder()
+            .peaks(vec![100.0, 200.0], vec![0.0, 0.0])
+            .build();
+        let unscaled = silent.normalize(NormalizationMode::BasePeak);
+        assert_eq!(unscaled.intensity_array(), Some(&[0.0, 0.0][..]));
+    }
+
+    #[test]
+    fn every_scan_window_is_captured() {
+        let window = |lower: &str, upper: &str| {
+            format!(
+                "<scanWindow>{}{}</scanWindow>",
+                cv_param_with_unit(accession::SCAN_WINDOW_LOWER_LIMIT, lower, accession::MZ),
+                cv_param_with_unit(accession::SCAN_WINDOW_UPPER_LIMIT, upper, accession::MZ)
+            )
+        };
+        let scan_list = format!(
+            r#"<scanList count="1"><scan><scanWindowList count="3">{}{}<scanWindow>{}</scanWindow></scanWindowList></scan></scanList>"#,
+            window("400", "450"),
+            window("600.5", "650.25"),
+            cv_param_with_unit(accession::SCAN_WINDOW_LOWER_LIMIT, "900", accession::MZ)
+        );
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[], &scan_list)]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let scan = &run.spectra[0].scan_list.as_ref().unwrap().scans[0];
+        assert_eq!(scan.scan_windows.len(), 3);
+        // The third window has no upper limit, so it has no range
+        assert_eq!(scan.mz_ranges(), [(400.0, 450.0), (600.5, 650.25)]);
+    }
+
+    /// Returns a scan start time param with the given value and unit
+    fn time_param(value: &str, unit: &str) -> CvParam {
+        CvParam {
+            cv_ref: "MS".into(),
+            accession: accession::SCAN_START_TIME.into(),
+            name: "scan start time".into(),
+            value: Some(value.to_string()),
+            unit_name: None,
+            unit_accession: Some(unit.into()),
+            unit_cv_ref: Some("UO".into()),
+        }
+    }
+
+    #[test]
+    fn value_in_unit_converts_minutes_to_seconds() {
+        let minutes = time_param("12.5", accession::MINUTE);
+        assert_eq!(minutes.value_as_f64(), Some(12.5));
+        assert_eq!(minutes.value_in_unit(accession::SECOND), Some(750.0));
+        assert_eq!(minutes.value_in_unit(accession::MINUTE), Some(12.5));
+        assert_eq!(
+            time_param("750", accession::SECOND).value_in_unit(accession::MINUTE),
+            Some(12.5)
+        );
+        assert_eq!(
+            time_param("1.5", accession::KILOVOLT).value_in_unit(accession::VOLT),
+            Some(1500.0)
+        );
+        // Time cannot become voltage, and a non-numeric value has no conversion
+        assert_eq!(minutes.value_in_unit(accession::VOLT), None);
+        assert_eq!(
+            time_param("n/a", accession::MINUTE).value_in_unit(accession::SECOND),
+            None
+        );
+    }
+
+    #[test]
+    fn second_intensity_stats_call_returns_the_cached_value() {
+        let mut spectrum = four_peaks();
+        assert!(spectrum.cache.intensity_stats.get().is_none());
+
+        let first = spectrum.intensity_stats().unwrap();
+        assert_eq!(spectrum.cache.intensity_stats.get(), Some(&first));
+
+        // Editing the array in place leaves the cache stale, which shows the array is not re-read
+        spectrum.binary_data_arrays[1].decoded_data = Some(vec![100.0; 4]);
+        assert_eq!(spectrum.intensity_stats(), Some(first));
+
+        spectrum.clear_cache();
+        assert_eq!(spectrum.intensity_stats().unwrap().max, 100.0);
+        // Clones start with an empty cache
+        assert!(spectrum.clone().cache.intensity_stats.get().is_none());
+    }
+
+    #[test]
+    fn spectrum_at_rt_searches_ascending_times() {
+        let run = Run::builder()
+            .spectra([
+                ms1_at("a", Some(10.0), vec![], vec![]),
+                ms1_at("untimed", None, vec![], vec![]),
+                ms1_at("b1", Some(20.0), vec![], vec![]),
+                ms1_at("b2", Some(20.0), vec![], vec![]),
+                ms1_at("c", Some(30.0), vec![], vec![]),
+            ])
+            .build();
+
+        assert_eq!(run.spectrum_at_rt(0.0).unwrap().id, "a");
+        assert_eq!(run.spectrum_at_rt(14.0).unwrap().id, "a");
+        // Midway between two scans, and between two scans at the same time, the earlier wins
+        assert_eq!(run.spectrum_at_rt(15.0).unwrap().id, "a");
+        assert_eq!(run.spectrum_at_rt(20.0).unwrap().id, "b1");
+        assert_eq!(run.spectrum_at_rt(24.0).unwrap().id, "b1");
+        assert_eq!(run.spectrum_at_rt(25.0).unwrap().id, "b1");
+        assert_eq!(run.spectrum_at_rt(26.0).unwrap().id, "c");
+        assert_eq!(run.spectrum_at_rt(99.0).unwrap().id, "c");
+    }
+
+    #[test]
+    fn spectrum_at_rt_scans_unsorted_times() {
+        let run = Run::builder()
+            .spectra([
+                ms1_at("c", Some(30.0), vec![], vec![]),
+                ms1_at("a", Some(10.0), vec![], vec![]),
+                ms1_at("b", Some(20.0), vec![], vec![]),
+            ])
+            .build();
+
+        assert_eq!(run.spectrum_at_rt(12.0).unwrap().id, "a");
+        assert_eq!(run.spectrum_at_rt(21.0).unwrap().id, "b");
+        assert_eq!(run.spectrum_at_rt(40.0).unwrap().id, "c");
+        // Equidistant from c and b, the earlier spectrum in the run is c
+        assert_eq!(run.spectrum_at_rt(25.0).unwrap().id, "c");
+    }
 }
@@ -0,0 +1,173 @@
+use crate::accession;
+use crate::error::{MzmlError, Result};
+use crate::models::{find_cv_param, BinaryDataArray, Run};
+use crate::options::{DecodeMode, ParseOptions};
+use hdf5::{Dataset, File};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+
+/// Reads an mzMLb file into a Run object, resolving its binary arrays from the HDF5 datasets
+///
+/// The XML is read from the "mzML" dataset. Each binary data array that names an external
+/// dataset, offset and array length is filled from that slice. Other arrays are decoded from
+/// their inline <binary> as usual.
+pub fn parse_mzmlb<P: AsRef<Path>>(path: P) -> Result<Run> {
+    // Step 1: read the embedded mzML document, leaving its arrays for below
+    let file = File::open(path.as_ref())?;
+    let xml = file.dataset("mzML")?.read_raw::<u8>()?;
+    let options = ParseOptions::builder()
+        .decode_mode(DecodeMode::Lazy)
+        .build();
+    let mut run = crate::parse_mzml_reader_with_options(&xml[..], &options)?;
+
+    // Step 2: fill every array, opening each dataset once
+    let mut datasets = HashMap::new();
+    let spectrum_arrays = run
+        .spectra
+        .iter_mut()
+        .flat_map(|s| s.binary_data_arrays.iter_mut());
+    let chromatogram_arrays = run
+        .chromatograms
+        .iter_mut()
+        .flat_map(|c| c.binary_data_arrays.iter_mut());
+    for array in spectrum_arrays.chain(chromatogram_arrays) {
+        read_external_array(&file, &mut datasets, array)?;
+    }
+
+    Ok(run)
+}
+
+/// Reads an array from the slice of the HDF5 dataset its cvParams point at, or decodes it inline
+fn read_external_array(
+    file: &File,
+    datasets: &mut HashMap<String, Dataset>,
+    array: &mut BinaryDataArray,
+) -> Result<()> {
+    let Some(name) = find_cv_param(&array.cv_params, accession::EXTERNAL_HDF5_DATASET)
+        .and_then(|p| p.value.clone())
+    else {
+        return array.decode();
+    };
+    let range = external_range(array)?;
+
+    let dataset = match datasets.get(&name) {
+        Some(dataset) => dataset,
+        None => {
+            let dataset = file.dataset(&name)?;
+            datasets.entry(name).or_insert(dataset)
+        }
+    };
+    // HDF5 converts the stored integer or float type to f64 as it reads
+    let values = dataset.read_slice_1d::<f64, _>(range)?;
+    array.decoded_data = Some(values.to_vec());
+    array.encoded_data = None;
+    Ok(())
+}
+
+/// Returns the slice of its dataset an array's external offset and length cvParams select
+fn external_range(array: &BinaryDataArray) -> Result<Range<usize>> {
+    let offset = external_value(array, accession::EXTERNAL_OFFSET, "external offset")?;
+    let length = external_value(
+        array,
+        accession::EXTERNAL_ARRAY_LENGTH,
+        "external array length",
+    )?;
+    // Both values come from the file, so an end past usize::MAX is bad input, not a panic
+    let end = offset
+        .checked_add(length)
+        .ok_or_else(|| MzmlError::InvalidAttribute {
+            name: "external array length".to_string(),
+            value: format!("{length} at offset {offset}"),
+        })?;
+    Ok(offset..end)
+}
+
+/// Reads a required external offset or length cvParam of an array
+fn external_value(array: &BinaryDataArray, accession: &str, name: &str) -> Result<usize> {
+    let value = find_cv_param(&array.cv_params, accession)
+        .and_then(|p| p.value.as_deref())
+        .ok_or_else(|| MzmlError::MissingAttribute(name.to_string()))?;
+    value.parse().map_err(|_| MzmlError::InvalidAttribute {
+        name: name.to_string(),
+        value: value.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{external_range, parse_mzmlb};
+    use crate::accession;
+    use crate::error::MzmlError;
+    use crate::test_fixtures::{cv_param, mzml_with_spectra};
+
+    /// Returns a spectrum whose arrays point at offset..offset + 2 of the "mz" and "intensity" datasets
+    fn external_spectrum(index: usize, offset: usize) -> String {
+        let array = |term: &str, precision: &str, dataset: &str| {
+            format!(
+                r#"<binaryDataArray encodedLength="0">{}{}{}{}{}{}<binary/></binaryDataArray>"#,
+                cv_param(term, ""),
+                cv_param(precision, ""),
+                cv_param(accession::NO_COMPRESSION, ""),
+                cv_param(accession::EXTERNAL_HDF5_DATASET, dataset),
+                cv_param(accession::EXTERNAL_OFFSET, &offset.to_string()),
+                cv_param(accession::EXTERNAL_ARRAY_LENGTH, "2"),
+            )
+        };
+        format!(
+            r#"<spectrum index="{index}" id="scan={}" defaultArrayLength="2"><binaryDataArrayList count="2">{}{}</binaryDataArrayList></spectrum>"#,
+            index + 1,
+            array(accession::MZ_ARRAY, accession::FLOAT_64_BIT, "mz"),
+            array(
+                accession::INTENSITY_ARRAY,
+                accession::FLOAT_32_BIT,
+                "intensity"
+            ),
+        )
+    }
+
+    #[test]
+    fn mzmlb_round_trip_resolves_external_arrays() {
+        let xml = mzml_with_spectra(&[external_spectrum(0, 0), external_spectrum(1, 2)]);
+        let path =
+            std::env::temp_dir().join(format!("libms-{}-round_trip.mzMLb", std::process::id()));
+
+        let file = hdf5::File::create(&path).unwrap();
+        let datasets = file.new_dataset_builder();
+        datasets.with_data(xml.as_bytes()).create("mzML").unwrap();
+        let datasets = file.new_dataset_builder();
+        datasets
+            .with_data(&[100.0f64, 200.0, 150.0, 250.0][..])
+            .create("mz")
+            .unwrap();
+        let datasets = file.new_dataset_builder();
+        datasets
+            .with_data(&[1.0f32, 2.0, 3.0, 4.0][..])
+            .create("intensity")
+            .unwrap();
+        drop(file);
+
+        let run = parse_mzmlb(&path).unwrap();
+        assert_eq!(run.spectra.len(), 2);
+        assert_eq!(run.spectra[0].mz_array(), Some(&[100.0, 200.0][..]));
+        assert_eq!(run.spectra[0].intensity_array(), Some(&[1.0, 2.0][..]));
+        assert_eq!(run.spectra[1].mz_array(), Some(&[150.0, 250.0][..]));
+        assert_eq!(run.spectra[1].intensity_array(), Some(&[3.0, 4.0][..]));
+    }
+
+    #[test]
+    fn overflowing_external_range_is_an_error() {
+        let xml = mzml_with_spectra(&[external_spectrum(0, 2), external_spectrum(1, usize::MAX)]);
+        let run = crate::parse_mzml(&xml).unwrap();
+
+        assert_eq!(
+            external_range(&run.spectra[0].binary_data_arrays[0]).unwrap(),
+            2..4
+        );
+        let err = external_range(&run.spectra[1].binary_data_arrays[0]).unwrap_err();
+        assert!(matches!(
+            err,
+            MzmlError::InvalidAttribute { ref name, .. } if name == "external array length"
+        ));
+    }
+}
@@ -0,0 +1,612 @@
+use crate::accession;
+use crate::error::{MzmlError, Result};
+use crate::models::{
+    Activation, BinaryDataArray, CvParam, Polarity, Precursor, Run, Scan, ScanList, Spectrum,
+    SpectrumCache,
+};
+use crate::parser::new_reader;
+use crate::utils::{
+    decode_binary_data_with_codecs, get_attr, get_attr_optional, get_attr_parsed, ByteOrder, Codec,
+    Precision,
+};
+use crate::writer::{end, start};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use quick_xml::events::{BytesDecl, BytesStart, BytesText, Event};
+use quick_xml::Writer;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+/// Parses an mzXML string into the same Run model used for mzML
+pub fn parse_mzxml(xml_data: &str) -> Result<Run> {
+    let mut reader = new_reader(xml_data.as_bytes());
+    let mut buf = Vec::new();
+    let mut run = None;
+    let mut spectra: Vec<Spectrum> = Vec::new();
+    // mzXML nests MSn scans inside their parent, so track open scans by position
+    let mut open_scans: Vec<usize> = Vec::new();
+    let mut peaks: Option<(PeaksFormat, String)> = None;
+    let mut precursor: Option<(BytesStart<'static>, String)> = None;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| MzmlError::from(e).at(reader.buffer_position()))?;
+        match &event {
+            Event::Eof => break,
+            Event::Start(e) => match e.name().as_ref() {
+                b"msRun" => {
+                    run = Some(Run {
+                        id: String::new(),
+                        start_time: String::new(),
+                        sample_ref: None,
+                        file_description: None,
+                        spectra: Vec::new(),
+                        chromatograms: Vec::new(),
+                    })
+                }
+                b"scan" => {
+                    let spectrum = scan_to_spectrum(e, spectra.len())
+                        .map_err(|err| err.at(reader.buffer_position()))?;
+                    open_scans.push(spectra.len());
+                    spectra.push(spectrum);
+                }
+                b"precursorMz" => precursor = Some((e.to_owned(), String::new())),
+                b"peaks" => {
+                    let format = PeaksFormat::from_attrs(e)
+                        .map_err(|err| err.at(reader.buffer_position()))?;
+                    peaks = Some((format, String::new()));
+                }
+                _ => {}
+            },
+            Event::Text(t) => {
+                let text = String::from_utf8_lossy(t);
+                if let Some((_, encoded)) = peaks.as_mut() {
+                    encoded.push_str(&text);
+                } else if let Some((_, value)) = precursor.as_mut() {
+                    value.push_str(&text);
+                }
+            }
+            Event::End(e) => match e.name().as_ref() {
+                b"scan" => {
+                    open_scans.pop();
+                }
+                b"precursorMz" => {
+                    if let (Some((e, value)), Some(&i)) = (precursor.take(), open_scans.last()) {
+                        let parsed = parse_precursor(&e, value.trim())
+                            .map_err(|err| err.at(reader.buffer_position()))?;
+                        spectra[i].precursors.push(parsed);
+                    }
+                }
+                b"peaks" => {
+                    if let (Some((format, encoded)), Some(&i)) = (peaks.take(), open_scans.last()) {
+                        spectra[i].binary_data_arrays = format
+                            .decode(&encoded)
+                            .map_err(|err| err.at(reader.buffer_position()))?;
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let mut run = run.ok_or(MzmlError::MissingRun)?;
+    run.spectra = spectra;
+    Ok(run)
+}
+
+/// Writes a Run as an mzXML 3.2 document with flat scans and 64-bit network-order peaks
+///
+/// Scans are numbered by the `scan=` token of their nativeIDs, or by position if those are
+/// missing or repeated. Only the first precursor of each spectrum is written.
+pub fn write_mzxml<W: Write>(run: &Run, writer: W) -> Result<()> {
+    let mut writer = Writer::new_with_indent(writer, b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+
+    // Step 1: number the scans so precursors can point at their parent scan
+    let mut nums: Vec<u64> = run
+        .spectra
+        .iter()
+        .filter_map(Spectrum::scan_number)
+        .collect();
+    let unique: HashSet<u64> = nums.iter().copied().collect();
+    if nums.len() != run.spectra.len() || unique.len() != nums.len() {
+        nums = (1..=run.spectra.len() as u64).collect();
+    }
+    let nums_by_id: HashMap<&str, u64> = run
+        .spectra
+        .iter()
+        .zip(&nums)
+        .map(|(s, &num)| (s.id.as_str(), num))
+        .collect();
+
+    start(
+        &mut writer,
+        "mzXML",
+        &[(
+            "xmlns",
+            "http://sashimi.sourceforge.net/schema_revision/mzXML_3.2",
+        )],
+    )?;
+    let scan_count = run.spectra.len().to_string();
+    let mut attributes = vec![("scanCount", scan_count)];
+    if let Some((first, last)) = run.retention_time_range() {
+        attributes.push(("startTime", format!("PT{}S", first)));
+        attributes.push(("endTime", format!("PT{}S", last)));
+    }
+    start(&mut writer, "msRun", &borrow_attributes(&attributes))?;
+
+    // Step 2: translate each spectrum's cvParams back into <scan> attributes
+    for (spectrum, num) in run.spectra.iter().zip(&nums) {
+        write_scan(&mut writer, spectrum, *num, &nums_by_id)?;
+    }
+
+    end(&mut writer, "msRun")?;
+    end(&mut writer, "mzXML")
+}
+
+fn write_scan<W: Write>(
+    writer: &mut Writer<W>,
+    spectrum: &Spectrum,
+    num: u64,
+    nums_by_id: &HashMap<&str, u64>,
+) -> Result<()> {
+    let mut attributes = vec![
+        ("num", num.to_string()),
+        ("peaksCount", spectrum.peak_count().to_string()),
+    ];
+    if let Some(level) = spectrum.ms_level() {
+        attributes.push(("msLevel", level.to_string()));
+    }
+    match spectrum.polarity() {
+        Some(Polarity::Positive) => attributes.push(("polarity", "+".to_string())),
+        Some(Polarity::Negative) => attributes.push(("polarity", "-".to_string())),
+        None => {}
+    }
+    if spectrum.is_centroided() {
+        attributes.push(("centroided", "1".to_string()));
+    } else if spectrum.is_profile() {
+        attributes.push(("centroided", "0".to_string()));
+    }
+    if let Some(retention_time) = spectrum.retention_time() {
+        attributes.push(("retentionTime", format!("PT{}S", retention_time)));
+    }
+    if let Some(mz) = spectrum.base_peak_mz() {
+        attributes.push(("basePeakMz", mz.to_string()));
+    }
+    if let Some(intensity) = spectrum.base_peak_intensity() {
+        attributes.push(("basePeakIntensity", intensity.to_string()));
+    }
+    if let Some(tic) = spectrum.total_ion_current() {
+        attributes.push(("totIonCurrent", tic.to_string()));
+    }
+    start(writer, "scan", &borrow_attributes(&attributes))?;
+
+    if let (Some(precursor), Some(mz)) = (spectrum.precursors.first(), spectrum.precursor_mz()) {
+        let mut attributes = Vec::new();
+        if let Some(num) = precursor
+            .spectrum_ref
+            .as_deref()
+            .and_then(|id| nums_by_id.get(id))
+        {
+            attributes.push(("precursorScanNum", num.to_string()));
+        }
+        if let Some(intensity) = spectrum.precursor_intensity() {
+            attributes.push(("precursorIntensity", intensity.to_string()));
+        }
+        if let Some(charge) = spectrum.precursor_charge() {
+            attributes.push(("precursorCharge", charge.to_string()));
+        }
+        let method = precursor
+            .activation
+            .cv_params
+            .iter()
+            .find_map(|p| match &*p.accession {
+                accession::CID => Some("CID"),
+                accession::HCD => Some("HCD"),
+                accession::ETD => Some("ETD"),
+                accession::ECD => Some("ECD"),
+                _ => None,
+            });
+        if let Some(method) = method {
+            attributes.push(("activationMethod", method.to_string()));
+        }
+        if let Some(window) = precursor.isolation_window_bounds() {
+            if let (Some(lower), Some(upper)) = (window.lower_offset, window.upper_offset) {
+                attributes.push(("windowWideness", (lower + upper).to_string()));
+            }
+        }
+        start(writer, "precursorMz", &borrow_attributes(&attributes))?;
+        writer.write_event(Event::Text(BytesText::new(&mz.to_string())))?;
+        end(writer, "precursorMz")?;
+    }
+
+    // mzXML interleaves m/z and intensity, in network (big-endian) order by default
+    let peaks: Vec<u8> = spectrum
+        .peaks()
+        .flat_map(|(mz, intensity)| [mz.to_be_bytes(), intensity.to_be_bytes()])
+        .flatten()
+        .collect();
+    start(
+        writer,
+        "peaks",
+        &[
+            ("precision", "64"),
+            ("byteOrder", "network"),
+            ("contentType", "m/z-int"),
+            ("compressionType", "none"),
+            ("compressedLen", "0"),
+        ],
+    )?;
+    writer.write_event(Event::Text(BytesText::new(&STANDARD.encode(peaks))))?;
+    end(writer, "peaks")?;
+    end(writer, "scan")
+}
+
+/// Borrows owned attribute values for start
+fn borrow_attributes<'a>(attributes: &'a [(&'a str, String)]) -> Vec<(&'a str, &'a str)> {
+    attributes
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect()
+}
+
+/// Encoding of a <peaks> block
+struct PeaksFormat {
+    precision: Precision,
+    compression: Option<Codec>,
+    byte_order: ByteOrder,
+}
+
+impl PeaksFormat {
+    fn from_attrs(e: &BytesStart) -> Result<Self> {
+        let precision = match get_attr_optional(e, "precision").as_deref() {
+            Some("64") => Precision::Float64,
+            None | Some("32") => Precision::Float32,
+            Some(other) => {
+                return Err(MzmlError::InvalidAttribute {
+                    name: "precision".to_string(),
+                    value: other.to_string(),
+                })
+            }
+        };
+        let compression = match get_attr_optional(e, "compressionType").as_deref() {
+            Some("zlib") => Some(Codec::Zlib),
+            _ => None,
+        };
+        // "network" order is big-endian, and is the mzXML default
+        let byte_order = match get_attr_optional(e, "byteOrder").as_deref() {
+            Some("little") => ByteOrder::LittleEndian,
+            _ => ByteOrder::BigEndian,
+        };
+        Ok(PeaksFormat {
+            precision,
+            compression,
+            byte_order,
+        })
+    }
+
+    /// Decodes interleaved m/z-intensity pairs into separate m/z and intensity arrays
+    fn decode(&self, encoded: &str) -> Result<Vec<BinaryDataArray>> {
+        let values = decode_binary_data_with_codecs(
+            encoded,
+            self.compression.as_slice(),
+            self.precision,
+            self.byte_order,
+        )?;
+        let (mz, intensity): (Vec<f64>, Vec<f64>) = values
+            .chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .unzip();
+
+        Ok(vec![
+            decoded_array(
+                unit(
+                    cv_param(accession::MZ_ARRAY, "m/z array", None),
+                    accession::MZ,
+                    "m/z",
+                ),
+                mz,
+            ),
+            decoded_array(
+                unit(
+                    cv_param(accession::INTENSITY_ARRAY, "intensity array", None),
+                    accession::NUMBER_OF_DETECTOR_COUNTS,
+                    "number of detector counts",
+                ),
+                intensity,
+            ),
+        ])
+    }
+}
+
+/// Maps the attributes of an mzXML <scan> onto an mzML-style spectrum
+fn scan_to_spectrum(e: &BytesStart, index: usize) -> Result<Spectrum> {
+    let num = get_attr(e, "num")?;
+    let mut cv_params = Vec::new();
+    let mut scan_params = Vec::new();
+
+    if let Some(level) = get_attr_optional(e, "msLevel") {
+        cv_params.push(cv_param(accession::MS_LEVEL, "ms level", Some(level)));
+    }
+    match get_attr_optional(e, "polarity").as_deref() {
+        Some("+") => cv_params.push(cv_param(accession::POSITIVE_SCAN, "positive scan", None)),
+        Some("-") => cv_params.push(cv_param(accession::NEGATIVE_SCAN, "negative scan", None)),
+        _ => {}
+    }
+    match get_attr_optional(e, "centroided").as_deref() {
+        Some("1") => cv_params.push(cv_param(
+            accession::CENTROID_SPECTRUM,
+            "centroid spectrum",
+            None,
+        )),
+        Some("0") => cv_params.push(cv_param(
+            accession::PROFILE_SPECTRUM,
+            "profile spectrum",
+            None,
+        )),
+        _ => {}
+    }
+    if let Some(mz) = get_attr_optional(e, "basePeakMz") {
+        let param = cv_param(accession::BASE_PEAK_MZ, "base peak m/z", Some(mz));
+        cv_params.push(unit(param, accession::MZ, "m/z"));
+    }
+    if let Some(intensity) = get_attr_optional(e, "basePeakIntensity") {
+        let param = cv_param(
+            accession::BASE_PEAK_INTENSITY,
+            "base peak intensity",
+            Some(intensity),
+        );
+        cv_params.push(unit(
+            param,
+            accession::NUMBER_OF_DETECTOR_COUNTS,
+            "number of detector counts",
+        ));
+    }
+    if let Some(tic) = get_attr_optional(e, "totIonCurrent") {
+        cv_params.push(cv_param(
+            accession::TOTAL_ION_CURRENT,
+            "total ion current",
+            Some(tic),
+        ));
+    }
+    if let Some(retention_time) = get_attr_optional(e, "retentionTime") {
+        let seconds =
+            parse_duration(&retention_time).ok_or_else(|| MzmlError::InvalidAttribute {
+                name: "retentionTime".to_string(),
+                value: retention_time.clone(),
+            })?;
+        let param = cv_param(
+            accession::SCAN_START_TIME,
+            "scan start time",
+            Some(seconds.to_string()),
+        );
+        scan_params.push(unit(param, accession::SECOND, "second"));
+    }
+
+    Ok(Spectrum {
+        id: format!("scan={}", num),
+        index,
+        default_array_length: get_attr_parsed(e, "peaksCount")?,
+        source_file_ref: None,
+        data_processing_ref: None,
+        cv_params,
+        user_params: Vec::new(),
+        scan_list: Some(ScanList {
+            count: 1,
+            cv_params: vec![cv_param(accession::NO_COMBINATION, "no combination", None)],
+            scans: vec![Scan {
+                instrument_configuration_ref: None,
+                cv_params: scan_params,
+                user_params: Vec::new(),
+                scan_windows: Vec::new(),
+            }],
+        }),
+        precursors: Vec::new(),
+        binary_data_arrays: Vec::new(),
+        cache: SpectrumCache::default(),
+    })
+}
+
+/// Builds a precursor from a <precursorMz> element and its m/z text
+fn parse_precursor(e: &BytesStart, mz: &str) -> Result<Precursor> {
+    mz.parse::<f64>().map_err(|_| MzmlError::InvalidAttribute {
+        name: "precursorMz".to_string(),
+        value: mz.to_string(),
+    })?;
+
+    let mut isolation_window = vec![unit(
+        cv_param(
+            accession::ISOLATION_WINDOW_TARGET_MZ,
+            "isolation window target m/z",
+            Some(mz.to_string()),
+        ),
+        accession::MZ,
+        "m/z",
+    )];
+    if let Some(width) = get_attr_optional(e, "windowWideness").and_then(|w| w.parse::<f64>().ok())
+    {
+        let offset = Some((width / 2.0).to_string());
+        isolation_window.push(unit(
+            cv_param(
+                accession::ISOLATION_WINDOW_LOWER_OFFSET,
+                "isolation window lower offset",
+                offset.clone(),
+            ),
+            accession::MZ,
+            "m/z",
+        ));
+        isolation_window.push(unit(
+            cv_param(
+                accession::ISOLATION_WINDOW_UPPER_OFFSET,
+                "isolation window upper offset",
+                offset,
+            ),
+            accession::MZ,
+            "m/z",
+        ));
+    }
+
+    let mut selected_ion = vec![unit(
+        cv_param(
+            accession::SELECTED_ION_MZ,
+            "selected ion m/z",
+            Some(mz.to_string()),
+        ),
+        accession::MZ,
+        "m/z",
+    )];
+    if let Some(charge) = get_attr_optional(e, "precursorCharge") {
+        selected_ion.push(cv_param(
+            accession::CHARGE_STATE,
+            "charge state",
+            Some(charge),
+        ));
+    }
+    if let Some(intensity) = get_attr_optional(e, "precursorIntensity") {
+        let param = cv_param(accession::PEAK_INTENSITY, "peak intensity", Some(intensity));
+        selected_ion.push(unit(
+            param,
+            accession::NUMBER_OF_DETECTOR_COUNTS,
+            "number of detector counts",
+        ));
+    }
+
+    let mut activation = Activation::default();
+    let method = match get_attr_optional(e, "activationMethod").as_deref() {
+        Some("CID") => Some((accession::CID, "collision-induced dissociation")),
+        Some("HCD") => Some((accession::HCD, "beam-type collision-induced dissociation")),
+        Some("ETD") => Some((accession::ETD, "electron transfer dissociation")),
+        Some("ECD") => Some((accession::ECD, "electron capture dissociation")),
+        _ => None,
+    };
+    if let Some((accession, name)) = method {
+        activation.cv_params.push(cv_param(accession, name, None));
+    }
+
+    Ok(Precursor {
+        spectrum_ref: get_attr_optional(e, "precursorScanNum").map(|num| format!("scan={}", num)),
+        isolation_window,
+        selected_ions: vec![selected_ion],
+        activation,
+    })
+}
+
+/// Parses an xs:duration such as "PT1M30.5S" into seconds
+fn parse_duration(duration: &str) -> Option<f64> {
+    let mut rest = duration.trim().strip_prefix("PT")?;
+    let mut seconds = 0.0;
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| c.is_ascii_alphabetic())?;
+        let value: f64 = rest[..end].parse().ok()?;
+        seconds += match &rest[end..end + 1] {
+            "H" => value * 3600.0,
+            "M" => value * 60.0,
+            "S" => value,
+            _ => return None,
+        };
+        rest = &rest[end + 1..];
+    }
+    Some(seconds)
+}
+
+fn decoded_array(kind: CvParam, values: Vec<f64>) -> BinaryDataArray {
+    BinaryDataArray {
+        encoded_length: 0,
+        cv_params: vec![
+            cv_param(accession::FLOAT_64_BIT, "64-bit float", None),
+            cv_param(accession::NO_COMPRESSION, "no compression", None),
+            kind,
+        ],
+        encoded_data: None,
+        decoded_data: Some(values),
+        raw_binary: None,
+    }
+}
+
+fn cv_param(accession: &str, name: &str, value: Option<String>) -> CvParam {
+    CvParam {
+        cv_ref: "MS".into(),
+        accession: accession.into(),
+        name: name.into(),
+        value,
+        unit_name: None,
+        unit_accession: None,
+        unit_cv_ref: None,
+    }
+}
+
+fn unit(mut param: CvParam, accession: &str, name: &str) -> CvParam {
+    param.unit_cv_ref = accession.split(':').next().map(Into::into);
+    param.unit_accession = Some(accession.into());
+    param.unit_name = Some(name.into());
+    param
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_mzxml, write_mzxml};
+    use crate::accession;
+    use crate::models::Polarity;
+    use crate::test_fixtures::{cv_param, cv_param_with_unit, mzml_with_spectra, spectrum};
+
+    #[test]
+    fn mzml_to_mzxml_round_trip_keeps_peaks() {
+        let scan = |seconds: &str| {
+            format!(
+                r#"<scanList count="1"><scan>{}</scan></scanList>"#,
+                cv_param_with_unit(accession::SCAN_START_TIME, seconds, accession::SECOND)
+            )
+        };
+        let ms1 = cv_param(accession::POSITIVE_SCAN, "")
+            + &cv_param(accession::CENTROID_SPECTRUM, "")
+            + &scan("60.5");
+        let ms2 = cv_param(accession::NEGATIVE_SCAN, "")
+            + &scan("61.25")
+            + &format!(
+                r#"<precursorList count="1"><precursor spectrumRef="scan=1"><selectedIonList count="1"><selectedIon>{}{}</selectedIon></selectedIonList><activation>{}</activation></precursor></precursorList>"#,
+                cv_param(accession::SELECTED_ION_MZ, "445.12"),
+                cv_param(accession::CHARGE_STATE, "2"),
+                cv_param(accession::CID, "")
+            );
+        let xml = mzml_with_spectra(&[
+            spectrum(
+                0,
+                1,
+                &[(100.125, 1.5), (445.12, 1e6), (1999.875, 3.0)],
+                &ms1,
+            ),
+            spectrum(1, 2, &[(120.0, 7.0), (300.5, 0.25)], &ms2),
+        ]);
+        let run = crate::parse_mzml(&xml).unwrap();
+
+        let mut out = Vec::new();
+        write_mzxml(&run, &mut out).unwrap();
+        let back = parse_mzxml(std::str::from_utf8(&out).unwrap()).unwrap();
+
+        assert_eq!(back.spectra.len(), 2);
+        for (original, written) in run.spectra.iter().zip(&back.spectra) {
+            assert_eq!(written.mz_array(), original.mz_array());
+            assert_eq!(written.intensity_array(), original.intensity_array());
+            assert_eq!(written.ms_level(), original.ms_level());
+            assert_eq!(written.retention_time(), original.retention_time());
+            assert_eq!(written.polarity(), original.polarity());
+        }
+        assert!(back.spectra[0].is_centroided());
+        assert_eq!(back.spectra[1].polarity(), Some(Polarity::Negative));
+        assert_eq!(back.spectra[1].precursor_mz(), Some(445.12));
+        assert_eq!(back.spectra[1].precursor_charge(), Some(2));
+    }
+
+    #[test]
+    fn mzxml_declares_the_utf8_it_writes() {
+        let run =
+            crate::parse_mzml(&mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 1.0)], "")])).unwrap();
+
+        let mut out = Vec::new();
+        write_mzxml(&run, &mut out).unwrap();
+        let written = String::from_utf8(out).unwrap();
+        assert!(written.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?>"#));
+    }
+}
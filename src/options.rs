@@ -0,0 +1,96 @@
+/// When binary data arrays are decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodeMode {
+    /// Decode each array as soon as its <binary> element is read
+    #[default]
+    Eager,
+    /// Keep arrays encoded until BinaryDataArray::decode is called
+    Lazy,
+}
+
+/// Whether list count attributes are checked against the items actually read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CountValidation {
+    /// Ignore count attributes, since some converters write wrong ones
+    #[default]
+    Off,
+    /// Fail the parse at the first list whose count is wrong
+    Strict,
+    /// Record wrong counts in MzML::count_mismatches and keep parsing
+    Lenient,
+}
+
+/// Options controlling how mzML is parsed
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    pub decode_mode: DecodeMode,
+    /// Trim leading and trailing whitespace from text nodes (on by default)
+    pub trim_text: bool,
+    /// Record cvParam accessions not in the built-in CV subset in MzML::unknown_accessions
+    pub collect_unknown_accessions: bool,
+    /// Keep each array's <binary> text in BinaryDataArray::raw_binary for verbatim re-export
+    pub keep_raw_binary: bool,
+    pub count_validation: CountValidation,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            decode_mode: DecodeMode::default(),
+            trim_text: true,
+            collect_unknown_accessions: false,
+            keep_raw_binary: false,
+            count_validation: CountValidation::default(),
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Starts building options from the defaults
+    pub fn builder() -> ParseOptionsBuilder {
+        ParseOptionsBuilder::default()
+    }
+}
+
+/// Builder for ParseOptions
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptionsBuilder {
+    options: ParseOptions,
+}
+
+impl ParseOptionsBuilder {
+    /// Sets when binary data arrays are decoded
+    pub fn decode_mode(mut self, decode_mode: DecodeMode) -> Self {
+        self.options.decode_mode = decode_mode;
+        self
+    }
+
+    /// Sets whether whitespace is trimmed from text nodes
+    pub fn trim_text(mut self, trim_text: bool) -> Self {
+        self.options.trim_text = trim_text;
+        self
+    }
+
+    /// Sets whether unrecognized cvParam accessions are collected
+    pub fn collect_unknown_accessions(mut self, collect: bool) -> Self {
+        self.options.collect_unknown_accessions = collect;
+        self
+    }
+
+    /// Sets whether the <binary> text of each array is kept alongside its decoded values
+    pub fn keep_raw_binary(mut self, keep: bool) -> Self {
+        self.options.keep_raw_binary = keep;
+        self
+    }
+
+    /// Sets whether list count attributes are checked
+    pub fn count_validation(mut self, count_validation: CountValidation) -> Self {
+        self.options.count_validation = count_validation;
+        self
+    }
+
+    /// Returns the configured options
+    pub fn build(self) -> ParseOptions {
+        self.options
+    }
+}
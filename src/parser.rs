@@ -0,0 +1,1148 @@
+use crate::cv;
+use crate::error::{CountMismatch, MzmlError, Result};
+use crate::models::{
+    Activation, BinaryDataArray, Chromatogram, Component, ComponentType, Cv, CvParam,
+    DataProcessing, FileDescription, InstrumentConfiguration, IsolationWindow, MzML, Precursor,
+    ProcessingMethod, Product, Run, Sample, Scan, ScanList, ScanWindow, Software, SourceFile,
+    Spectrum, SpectrumCache,
+};
+use crate::options::{CountValidation, DecodeMode, ParseOptions};
+use crate::utils::{
+    get_attr, get_attr_optional, get_attr_parsed, parse_cv_param_pooled, parse_user_param,
+    StringPool,
+};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+
+/// Upper bound on the spectra preallocated from an untrusted spectrumList count
+///
+/// A count is only a hint, so a tiny document claiming billions of spectra must not reserve
+/// more than a few MB; larger runs grow the vector as usual.
+const MAX_PREALLOCATED_SPECTRA: usize = 4096;
+
+/// A list element with a count attribute whose items are being counted
+struct OpenList {
+    element: String,
+    declared: usize,
+    actual: usize,
+    /// Nesting depth of the list element itself
+    depth: usize,
+}
+
+/// Elements that own the cvParams nested directly inside them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParamContext {
+    Spectrum,
+    Chromatogram,
+    ScanList,
+    Scan,
+    ScanWindow,
+    IsolationWindow,
+    SelectedIon,
+    Activation,
+    BinaryDataArray,
+    ParamGroup,
+    FileContent,
+    SourceFile,
+    Sample,
+    Software,
+    InstrumentConfiguration,
+    Component,
+    ProcessingMethod,
+}
+
+/// Creates a quick-xml reader configured for mzML
+pub(crate) fn new_reader<R: BufRead>(reader: R) -> Reader<R> {
+    new_reader_with_options(reader, &ParseOptions::default())
+}
+
+/// Creates a quick-xml reader configured for mzML using the given options
+pub(crate) fn new_reader_with_options<R>(reader: R, options: &ParseOptions) -> Reader<R> {
+    let mut reader = Reader::from_reader(reader);
+    reader.config_mut().trim_text(options.trim_text);
+    reader
+}
+
+/// Incremental mzML parser state, fed one XML event at a time
+#[derive(Default)]
+pub(crate) struct MzmlParser {
+    options: ParseOptions,
+    run: Option<Run>,
+    current_spectrum: Option<Spectrum>,
+    current_chromatogram: Option<Chromatogram>,
+    current_scan_list: Option<ScanList>,
+    current_scan: Option<Scan>,
+    current_scan_window: Option<ScanWindow>,
+    current_precursor: Option<Precursor>,
+    /// The isolation window params of the <product> being read
+    current_product: Option<Vec<CvParam>>,
+    current_binary_data_array: Option<BinaryDataArray>,
+    current_binary: Option<String>,
+    context: Vec<ParamContext>,
+    param_groups: HashMap<String, Vec<CvParam>>,
+    file_description: Option<FileDescription>,
+    current_source_file: Option<SourceFile>,
+    current_param_group: Option<(String, Vec<CvParam>)>,
+    cv_list: Vec<Cv>,
+    samples: Vec<Sample>,
+    software: Vec<Software>,
+    instrument_configurations: Vec<InstrumentConfiguration>,
+    data_processing: Vec<DataProcessing>,
+    current_sample: Option<Sample>,
+    current_software: Option<Software>,
+    current_instrument_configuration: Option<InstrumentConfiguration>,
+    current_component: Option<Component>,
+    current_data_processing: Option<DataProcessing>,
+    current_processing_method: Option<ProcessingMethod>,
+    spectrum_list_count: Option<usize>,
+    strings: StringPool,
+    unknown_accessions: HashSet<String>,
+    /// Number of elements currently open, tracked only while counts are validated
+    depth: usize,
+    open_lists: Vec<OpenList>,
+    count_mismatches: Vec<CountMismatch>,
+}
+
+impl MzmlParser {
+    /// Creates a parser that follows the given options
+    pub(crate) fn with_options(options: ParseOptions) -> Self {
+        MzmlParser {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a parser that already knows the referenceableParamGroups of a document
+    pub(crate) fn with_param_groups(param_groups: HashMap<String, Vec<CvParam>>) -> Self {
+        MzmlParser {
+            param_groups,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the referenceableParamGroups read so far
+    pub(crate) fn param_groups(&self) -> &HashMap<String, Vec<CvParam>> {
+        &self.param_groups
+    }
+
+    /// Handles one event, returning a spectrum once its closing tag is reached
+    pub(crate) fn handle_event(&mut self, event: &Event) -> Result<Option<Spectrum>> {
+        if self.options.count_validation != CountValidation::Off {
+            self.count_list_items(event)?;
+        }
+        self.apply_event(event)
+    }
+
+    /// Updates the document being built with one event
+    fn apply_event(&mut self, event: &Event) -> Result<Option<Spectrum>> {
+        match event {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"cvParam" => {
+                let param = parse_cv_param_pooled(e, &mut self.strings)?;
+                if self.options.collect_unknown_accessions
+                    && cv::lookup(&param.accession).is_none()
+                    && !self.unknown_accessions.contains(&*param.accession)
+                {
+                    self.unknown_accessions.insert(param.accession.to_string());
+                }
+                self.push_cv_param(param);
+            }
+            Event::Start(e) | Event::Empty(e)
+                if e.name().as_ref() == b"referenceableParamGroupRef" =>
+            {
+                let id = get_attr(e, "ref")?;
+                if let Some(params) = self.param_groups.get(&id).cloned() {
+                    for param in params {
+                        self.push_cv_param(param);
+                    }
+                }
+            }
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"userParam" => {
+                let param = parse_user_param(e)?;
+                match self.context.last() {
+                    Some(ParamContext::Spectrum) => {
+                        if let Some(spectrum) = self.current_spectrum.as_mut() {
+                            spectrum.user_params.push(param);
+                        }
+                    }
+                    Some(ParamContext::Scan) => {
+                        if let Some(scan) = self.current_scan.as_mut() {
+                            scan.user_params.push(param);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"cv" => {
+                self.cv_list.push(Cv {
+                    id: get_attr(e, "id")?,
+                    full_name: get_attr(e, "fullName")?,
+                    version: get_attr_optional(e, "version"),
+                    uri: get_attr(e, "URI")?,
+                });
+            }
+            Event::Start(e) => match e.name().as_ref() {
+                b"sample" => {
+                    self.current_sample = Some(Sample {
+                        id: get_attr(e, "id")?,
+                        name: get_attr_optional(e, "name"),
+                        cv_params: Vec::new(),
+                    });
+                    self.context.push(ParamContext::Sample);
+                }
+                b"software" => {
+                    self.current_software = Some(Software {
+                        id: get_attr(e, "id")?,
+                        version: get_attr(e, "version")?,
+                        cv_params: Vec::new(),
+                    });
+                    self.context.push(ParamContext::Software);
+                }
+                b"instrumentConfiguration" => {
+                    self.current_instrument_configuration = Some(InstrumentConfiguration {
+                        id: get_attr(e, "id")?,
+                        cv_params: Vec::new(),
+                        components: Vec::new(),
+                    });
+                    self.context.push(ParamContext::InstrumentConfiguration);
+                }
+                name @ (b"source" | b"analyzer" | b"detector") => {
+                    let component_type = match name {
+                        b"source" => ComponentType::Source,
+                        b"analyzer" => ComponentType::Analyzer,
+                        _ => ComponentType::Detector,
+                    };
+                    self.current_component = Some(Component {
+                        component_type,
+                        order: get_attr_parsed(e, "order")?,
+                        cv_params: Vec::new(),
+                    });
+                    self.context.push(ParamContext::Component);
+                }
+                b"dataProcessing" => {
+                    self.current_data_processing = Some(DataProcessing {
+                        id: get_attr(e, "id")?,
+                        processing_methods: Vec::new(),
+                    });
+                }
+                b"processingMethod" => {
+                    self.current_processing_method = Some(ProcessingMethod {
+                        order: get_attr_parsed(e, "order")?,
+                        software_ref: get_attr(e, "softwareRef")?,
+                        cv_params: Vec::new(),
+                    });
+                    self.context.push(ParamContext::ProcessingMethod);
+                }
+                b"fileDescription" => self.file_description = Some(FileDescription::default()),
+                b"fileContent" => self.context.push(ParamContext::FileContent),
+                b"sourceFile" => {
+                    self.current_source_file = Some(SourceFile {
+                        id: get_attr(e, "id")?,
+                        name: get_attr(e, "name")?,
+                        location: get_attr(e, "location")?,
+                        cv_params: Vec::new(),
+                    });
+                    self.context.push(ParamContext::SourceFile);
+                }
+                b"referenceableParamGroup" => {
+                    self.current_param_group = Some((get_attr(e, "id")?, Vec::new()));
+                    self.context.push(ParamContext::ParamGroup);
+                }
+                b"run" => {
+                    self.run = Some(Run {
+                        id: get_attr(e, "id")?,
+                        start_time: get_attr(e, "startTimeStamp")?,
+                        sample_ref: get_attr_optional(e, "sampleRef"),
+                        file_description: self.file_description.take(),
+                        spectra: Vec::new(),
+                        chromatograms: Vec::new(),
+                    });
+                }
+                b"spectrumList" => {
+                    // The count is only a capacity hint, so a bad value is ignored
+                    self.spectrum_list_count = get_attr_optional(e, "count")
+                        .and_then(|count| count.parse().ok())
+                        .map(|count: usize| count.min(MAX_PREALLOCATED_SPECTRA));
+                }
+                b"spectrum" => {
+                    let id = get_attr(e, "id")?;
+                    let in_spectrum = |error| MzmlError::InSpectrum {
+                        spectrum_id: id.clone(),
+                        error: Box::new(error),
+                    };
+                    let index = get_attr_parsed(e, "index").map_err(in_spectrum)?;
+                    let default_array_length =
+                        get_attr_parsed(e, "defaultArrayLength").map_err(in_spectrum)?;
+                    self.current_spectrum = Some(Spectrum {
+                        id,
+                        index,
+                        default_array_length,
+                        source_file_ref: get_attr_optional(e, "sourceFileRef"),
+                        data_processing_ref: get_attr_optional(e, "dataProcessingRef"),
+                        cv_params: Vec::new(),
+                        user_params: Vec::new(),
+                        scan_list: None,
+                        precursors: Vec::new(),
+                        binary_data_arrays: Vec::new(),
+                        cache: SpectrumCache::default(),
+                    });
+                    self.context.push(ParamContext::Spectrum);
+                }
+                b"chromatogram" => {
+                    self.current_chromatogram = Some(Chromatogram {
+                        id: get_attr(e, "id")?,
+                        index: get_attr_parsed(e, "index")?,
+                        default_array_length: get_attr_parsed(e, "defaultArrayLength")?,
+                        cv_params: Vec::new(),
+                        precursors: Vec::new(),
+                        products: Vec::new(),
+                        binary_data_arrays: Vec::new(),
+                    });
+                    self.context.push(ParamContext::Chromatogram);
+                }
+                b"scanList" => {
+                    self.current_scan_list = Some(ScanList {
+                        count: get_attr_parsed(e, "count")?,
+                        cv_params: Vec::new(),
+                        scans: Vec::new(),
+                    });
+                    self.context.push(ParamContext::ScanList);
+                }
+                b"scan" => {
+                    self.current_scan = Some(Scan {
+                        instrument_configuration_ref: get_attr_optional(
+                            e,
+                            "instrumentConfigurationRef",
+                        ),
+                        cv_params: Vec::new(),
+                        user_params: Vec::new(),
+                        scan_windows: Vec::new(),
+                    });
+                    self.context.push(ParamContext::Scan);
+                }
+                b"scanWindow" => {
+                    self.current_scan_window = Some(ScanWindow {
+                        cv_params: Vec::new(),
+                    });
+                    self.context.push(ParamContext::ScanWindow);
+                }
+                b"precursor" => {
+                    self.current_precursor = Some(Precursor {
+                        spectrum_ref: get_attr_optional(e, "spectrumRef"),
+                        isolation_window: Vec::new(),
+                        selected_ions: Vec::new(),
+                        activation: Activation::default(),
+                    });
+                }
+                b"product" => self.current_product = Some(Vec::new()),
+                b"isolationWindow" => self.context.push(ParamContext::IsolationWindow),
+                b"selectedIon" => {
+                    if let Some(precursor) = self.current_precursor.as_mut() {
+                        precursor.selected_ions.push(Vec::new());
+                    }
+                    self.context.push(ParamContext::SelectedIon);
+                }
+                b"activation" => self.context.push(ParamContext::Activation),
+                b"binaryDataArray" => {
+                    self.current_binary_data_array = Some(BinaryDataArray {
+                        encoded_length: get_attr_parsed(e, "encodedLength")?,
+                        cv_params: Vec::new(),
+                        encoded_data: None,
+                        decoded_data: None,
+                        raw_binary: None,
+                    });
+                    self.context.push(ParamContext::BinaryDataArray);
+                }
+                b"binary" => self.current_binary = Some(String::new()),
+                _ => {}
+            },
+            Event::Text(t) => {
+                if let Some(binary) = self.current_binary.as_mut() {
+                    binary.push_str(&String::from_utf8_lossy(t));
+                }
+            }
+            Event::End(e) => match e.name().as_ref() {
+                b"sample" => {
+                    self.context.pop();
+                    if let Some(sample) = self.current_sample.take() {
+                        self.samples.push(sample);
+                    }
+                }
+                b"software" => {
+                    self.context.pop();
+                    if let Some(software) = self.current_software.take() {
+                        self.software.push(software);
+                    }
+                }
+                b"instrumentConfiguration" => {
+                    self.context.pop();
+                    if let Some(configuration) = self.current_instrument_configuration.take() {
+                        self.instrument_configurations.push(configuration);
+                    }
+                }
+                b"source" | b"analyzer" | b"detector" => {
+                    self.context.pop();
+                    if let Some(component) = self.current_component.take() {
+                        if let Some(configuration) = self.current_instrument_configuration.as_mut()
+                        {
+                            configuration.components.push(component);
+                        }
+                    }
+                }
+                b"processingMethod" => {
+                    self.context.pop();
+                    if let Some(processing_method) = self.current_processing_method.take() {
+                        if let Some(data_processing) = self.current_data_processing.as_mut() {
+                            data_processing.processing_methods.push(processing_method);
+                        }
+                    }
+                }
+                b"dataProcessing" => {
+                    if let Some(data_processing) = self.current_data_processing.take() {
+                        self.data_processing.push(data_processing);
+                    }
+                }
+                b"fileContent" => {
+                    self.context.pop();
+                }
+                b"sourceFile" => {
+                    self.context.pop();
+                    if let Some(source_file) = self.current_source_file.take() {
+                        if let Some(file_description) = self.file_description.as_mut() {
+                            file_description.source_files.push(source_file);
+                        }
+                    }
+                }
+                b"referenceableParamGroup" => {
+                    self.context.pop();
+                    if let Some((id, params)) = self.current_param_group.take() {
+                        self.param_groups.insert(id, params);
+                    }
+                }
+                b"binary" => {
+                    let encoded_data = self.current_binary.take().unwrap_or_default();
+                    if let Some(array) = self.current_binary_data_array.as_mut() {
+                        if self.options.keep_raw_binary {
+                            array.raw_binary = Some(encoded_data.clone());
+                        }
+                        array.encoded_data = Some(encoded_data);
+                        if self.options.decode_mode == DecodeMode::Eager {
+                            array.decode()?;
+                        }
+                    }
+                }
+                b"spectrum" => {
+                    self.context.pop();
+                    return Ok(self.current_spectrum.take());
+                }
+                b"chromatogram" => {
+                    self.context.pop();
+                    if let Some(chromatogram) = self.current_chromatogram.take() {
+                        if let Some(run) = self.run.as_mut() {
+                            run.chromatograms.push(chromatogram);
+                        }
+                    }
+                }
+                b"scanList" => {
+                    self.context.pop();
+                    if let Some(scan_list) = self.current_scan_list.take() {
+                        if let Some(spectrum) = self.current_spectrum.as_mut() {
+                            spectrum.scan_list = Some(scan_list);
+                        }
+                    }
+                }
+                b"scan" => {
+                    self.context.pop();
+                    if let Some(scan) = self.current_scan.take() {
+                        if let Some(scan_list) = self.current_scan_list.as_mut() {
+                            scan_list.scans.push(scan);
+                        }
+                    }
+                }
+                b"scanWindow" => {
+                    self.context.pop();
+                    if let Some(scan_window) = self.current_scan_window.take() {
+                        if let Some(scan) = self.current_scan.as_mut() {
+                            scan.scan_windows.push(scan_window);
+                        }
+                    }
+                }
+                b"precursor" => {
+                    if let Some(precursor) = self.current_precursor.take() {
+                        if let Some(spectrum) = self.current_spectrum.as_mut() {
+                            spectrum.precursors.push(precursor);
+                        } else if let Some(chromatogram) = self.current_chromatogram.as_mut() {
+                            chromatogram.precursors.push(precursor);
+                        }
+                    }
+                }
+                b"product" => {
+                    if let Some(params) = self.current_product.take() {
+                        let isolation_window =
+                            IsolationWindow::from_cv_params(&params).unwrap_or_default();
+                        if let Some(chromatogram) = self.current_chromatogram.as_mut() {
+                            chromatogram.products.push(Product { isolation_window });
+                        }
+                    }
+                }
+                b"isolationWindow" | b"selectedIon" | b"activation" => {
+                    self.context.pop();
+                }
+                b"binaryDataArray" => {
+                    self.context.pop();
+                    if let Some(array) = self.current_binary_data_array.take() {
+                        if let Some(spectrum) = self.current_spectrum.as_mut() {
+                            spectrum.binary_data_arrays.push(array);
+                        } else if let Some(chromatogram) = self.current_chromatogram.as_mut() {
+                            chromatogram.binary_data_arrays.push(array);
+                        }
+                    }
+                }
+                _ => {}
+            },
+            // A self-closing container, e.g. <spectrum .../> or <binary/>, opens and closes at once
+            Event::Empty(e) => {
+                self.apply_event(&Event::Start(e.borrow()))?;
+                return self.apply_event(&Event::End(e.to_end()));
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Counts the items of each open list, checking the total against its count when it closes
+    ///
+    /// Params are not list items, e.g. the cvParam of a <scanList> next to its <scan>s.
+    fn count_list_items(&mut self, event: &Event) -> Result<()> {
+        let (e, opens) = match event {
+            Event::Start(e) => (e, true),
+            Event::Empty(e) => (e, false),
+            Event::End(_) => {
+                self.depth = self.depth.saturating_sub(1);
+                let depth = self.depth;
+                if self.open_lists.last().is_some_and(|l| l.depth == depth) {
+                    if let Some(list) = self.open_lists.pop() {
+                        return self.check_list_count(list);
+                    }
+                }
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+
+        // Step 1: count the element as an item of the list directly around it
+        let name = e.name();
+        let is_param = matches!(
+            name.as_ref(),
+            b"cvParam" | b"userParam" | b"referenceableParamGroupRef"
+        );
+        if let Some(list) = self.open_lists.last_mut() {
+            if list.depth + 1 == self.depth && !is_param {
+                list.actual += 1;
+            }
+        }
+
+        // Step 2: start counting the items of a list, or check an empty one straight away
+        let declared = if name.as_ref().ends_with(b"List") {
+            get_attr_optional(e, "count").and_then(|c| c.parse().ok())
+        } else {
+            None
+        };
+        if let Some(declared) = declared {
+            let list = OpenList {
+                element: String::from_utf8_lossy(name.as_ref()).into_owned(),
+                declared,
+                actual: 0,
+                depth: self.depth,
+            };
+            if !opens {
+                self.check_list_count(list)?;
+            } else {
+                self.open_lists.push(list);
+            }
+        }
+        if opens {
+            self.depth += 1;
+        }
+        Ok(())
+    }
+
+    /// Reports a closed list whose count attribute disagrees with its items
+    fn check_list_count(&mut self, list: OpenList) -> Result<()> {
+        if list.actual == list.declared {
+            return Ok(());
+        }
+        let mismatch = CountMismatch {
+            element: list.element,
+            declared: list.declared,
+            actual: list.actual,
+        };
+        match self.options.count_validation {
+            CountValidation::Strict => Err(mismatch.into()),
+            _ => {
+                self.count_mismatches.push(mismatch);
+                Ok(())
+            }
+        }
+    }
+
+    /// Attaches a cvParam to the element at the top of the context stack
+    fn push_cv_param(&mut self, param: CvParam) {
+        match self.context.last() {
+            Some(ParamContext::Spectrum) => {
+                if let Some(spectrum) = self.current_spectrum.as_mut() {
+                    spectrum.cv_params.push(param);
+                }
+            }
+            Some(ParamContext::Chromatogram) => {
+                if let Some(chromatogram) = self.current_chromatogram.as_mut() {
+                    chromatogram.cv_params.push(param);
+                }
+            }
+            Some(ParamContext::BinaryDataArray) => {
+                if let Some(array) = self.current_binary_data_array.as_mut() {
+                    array.cv_params.push(param);
+                }
+            }
+            Some(ParamContext::ScanList) => {
+                if let Some(scan_list) = self.current_scan_list.as_mut() {
+                    scan_list.cv_params.push(param);
+                }
+            }
+            Some(ParamContext::Scan) => {
+                if let Some(scan) = self.current_scan.as_mut() {
+                    scan.cv_params.push(param);
+                }
+            }
+            Some(ParamContext::ScanWindow) => {
+                if let Some(scan_window) = self.current_scan_window.as_mut() {
+                    scan_window.cv_params.push(param);
+                }
+            }
+            Some(ParamContext::IsolationWindow) => {
+                if let Some(product) = self.current_product.as_mut() {
+                    product.push(param);
+                } else if let Some(precursor) = self.current_precursor.as_mut() {
+                    precursor.isolation_window.push(param);
+                }
+            }
+            Some(ParamContext::SelectedIon) => {
+                if let Some(precursor) = self.current_precursor.as_mut() {
+                    if let Some(selected_ion) = precursor.selected_ions.last_mut() {
+                        selected_ion.push(param);
+                    }
+                }
+            }
+            Some(ParamContext::Activation) => {
+                if let Some(precursor) = self.current_precursor.as_mut() {
+                    precursor.activation.cv_params.push(param);
+                }
+            }
+            Some(ParamContext::ParamGroup) => {
+                if let Some((_, params)) = self.current_param_group.as_mut() {
+                    params.push(param);
+                }
+            }
+            Some(ParamContext::Sample) => {
+                if let Some(sample) = self.current_sample.as_mut() {
+                    sample.cv_params.push(param);
+                }
+            }
+            Some(ParamContext::Software) => {
+                if let Some(software) = self.current_software.as_mut() {
+                    software.cv_params.push(param);
+                }
+            }
+            Some(ParamContext::InstrumentConfiguration) => {
+                if let Some(configuration) = self.current_instrument_configuration.as_mut() {
+                    configuration.cv_params.push(param);
+                }
+            }
+            Some(ParamContext::Component) => {
+                if let Some(component) = self.current_component.as_mut() {
+                    component.cv_params.push(param);
+                }
+            }
+            Some(ParamContext::ProcessingMethod) => {
+                if let Some(processing_method) = self.current_processing_method.as_mut() {
+                    processing_method.cv_params.push(param);
+                }
+            }
+            Some(ParamContext::FileContent) => {
+                if let Some(file_description) = self.file_description.as_mut() {
+                    file_description.file_content.push(param);
+                }
+            }
+            Some(ParamContext::SourceFile) => {
+                if let Some(source_file) = self.current_source_file.as_mut() {
+                    source_file.cv_params.push(param);
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Returns the spectrumList count once its start tag has been read, at most once
+    ///
+    /// The count is capped at MAX_PREALLOCATED_SPECTRA, so it is safe to reserve directly.
+    pub(crate) fn take_spectrum_list_count(&mut self) -> Option<usize> {
+        self.spectrum_list_count.take()
+    }
+
+    /// Discards the spectrum being parsed so parsing can resume after its </spectrum>
+    pub(crate) fn abandon_spectrum(&mut self) {
+        self.current_spectrum = None;
+        self.current_scan_list = None;
+        self.current_scan = None;
+        self.current_scan_window = None;
+        self.current_precursor = None;
+        self.current_product = None;
+        self.current_binary_data_array = None;
+        self.current_binary = None;
+        if let Some(position) = self
+            .context
+            .iter()
+            .position(|c| *c == ParamContext::Spectrum)
+        {
+            self.context.truncate(position);
+        }
+    }
+
+    /// Returns the document, with a run without spectra, once parsing is done
+    pub(crate) fn finish_document(self) -> Result<MzML> {
+        let run = self.run.ok_or(MzmlError::MissingRun)?;
+        Ok(MzML {
+            cv_list: self.cv_list,
+            samples: self.samples,
+            software: self.software,
+            instrument_configurations: self.instrument_configurations,
+            data_processing: self.data_processing,
+            run,
+            unknown_accessions: self.unknown_accessions,
+            count_mismatches: self.count_mismatches,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::accession;
+    use crate::error::MzmlError;
+    use crate::models::{ComponentType, Polarity};
+    use crate::options::{CountValidation, ParseOptions};
+    use crate::test_fixtures::{
+        binary_array, cv_param, cv_param_with_unit, f32_base64, f64_base64, mrm_chromatogram, mzml,
+        mzml_with_header, mzml_with_spectra, spectrum,
+    };
+    use crate::utils::Precision;
+
+    #[test]
+    fn array_params_stay_on_their_own_array() {
+        let arrays = [
+            binary_array(
+                &[
+                    accession::MZ_ARRAY,
+                    accession::FLOAT_64_BIT,
+                    accession::NO_COMPRESSION,
+                ],
+                &f64_base64(&[100.0, 200.0]),
+            ),
+            binary_array(
+                &[
+                    accession::INTENSITY_ARRAY,
+                    accession::FLOAT_32_BIT,
+                    accession::NO_COMPRESSION,
+                ],
+                &f32_base64(&[10.0, 20.0]),
+            ),
+        ];
+        let xml = mzml_with_spectra(&[format!(
+            r#"<spectrum index="0" id="scan=1" defaultArrayLength="2"><binaryDataArrayList count="2">{}</binaryDataArrayList></spectrum>"#,
+            arrays.concat()
+        )]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let spectrum = &run.spectra[0];
+        assert!(spectrum.cv_params.is_empty());
+        let [mz, intensity] = &spectrum.binary_data_arrays[..] else {
+            panic!("expected two arrays");
+        };
+        assert_eq!(mz.cv_params.len(), 3);
+        assert_eq!(mz.precision(), Some(Precision::Float64));
+        assert_eq!(intensity.cv_params.len(), 3);
+        assert_eq!(intensity.precision(), Some(Precision::Float32));
+        assert_eq!(spectrum.mz_array(), Some(&[100.0, 200.0][..]));
+        assert_eq!(spectrum.intensity_array(), Some(&[10.0, 20.0][..]));
+    }
+
+    #[test]
+    fn scan_params_are_parsed_into_the_scan_list() {
+        let scan_list = format!(
+            r#"<scanList count="1"><scan>{}</scan></scanList>"#,
+            cv_param_with_unit(accession::SCAN_START_TIME, "12.5", accession::SECOND)
+        );
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 1.0)], &scan_list)]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let scan_list = run.spectra[0].scan_list.as_ref().unwrap();
+        assert_eq!(scan_list.scans.len(), 1);
+        let params = &scan_list.scans[0].cv_params;
+        assert_eq!(params.len(), 1);
+        assert_eq!(&*params[0].accession, accession::SCAN_START_TIME);
+        assert_eq!(params[0].value.as_deref(), Some("12.5"));
+    }
+
+    #[test]
+    fn tic_chromatogram_arrays_are_populated() {
+        let arrays = [
+            binary_array(
+                &[
+                    accession::TIME_ARRAY,
+                    accession::FLOAT_64_BIT,
+                    accession::NO_COMPRESSION,
+                ],
+                &f64_base64(&[0.5, 1.0, 1.5]),
+            ),
+            binary_array(
+                &[
+                    accession::INTENSITY_ARRAY,
+                    accession::FLOAT_64_BIT,
+                    accession::NO_COMPRESSION,
+                ],
+                &f64_base64(&[10.0, 30.0, 20.0]),
+            ),
+        ];
+        let xml = mzml(&format!(
+            r#"<chromatogramList count="1"><chromatogram index="0" id="TIC" defaultArrayLength="3">{}<binaryDataArrayList count="2">{}</binaryDataArrayList></chromatogram></chromatogramList>"#,
+            cv_param(accession::TOTAL_ION_CURRENT_CHROMATOGRAM, ""),
+            arrays.concat()
+        ));
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let tic = &run.chromatograms[0];
+        assert_eq!(tic.id, "TIC");
+        assert_eq!(
+            &*tic.cv_params[0].accession,
+            accession::TOTAL_ION_CURRENT_CHROMATOGRAM
+        );
+        assert_eq!(tic.time_array(), Some(&[0.5, 1.0, 1.5][..]));
+        assert_eq!(tic.intensity_array(), Some(&[10.0, 30.0, 20.0][..]));
+    }
+
+    #[test]
+    fn user_params_are_kept_with_their_value() {
+        let user_param = r#"<userParam name="[Thermo Trailer Extra]Monoisotopic M/Z:" value="445.1200" type="xsd:float"/>"#;
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[], user_param)]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let params = &run.spectra[0].user_params;
+        assert_eq!(params.len(), 1);
+        assert_eq!(params[0].name, "[Thermo Trailer Extra]Monoisotopic M/Z:");
+        assert_eq!(params[0].value.as_deref(), Some("445.1200"));
+        assert_eq!(params[0].type_.as_deref(), Some("xsd:float"));
+    }
+
+    #[test]
+    fn param_groups_are_applied_to_every_referencing_spectrum() {
+        let header = format!(
+            r#"<referenceableParamGroupList count="1"><referenceableParamGroup id="MS1">{}{}</referenceableParamGroup></referenceableParamGroupList>"#,
+            cv_param(accession::POSITIVE_SCAN, ""),
+            cv_param(accession::CENTROID_SPECTRUM, "")
+        );
+        let group_ref = r#"<referenceableParamGroupRef ref="MS1"/>"#;
+        let spectra = [
+            spectrum(0, 1, &[], group_ref),
+            spectrum(1, 1, &[], group_ref),
+        ];
+        let xml = mzml_with_header(
+            &header,
+            &format!(
+                r#"<spectrumList count="2">{}</spectrumList>"#,
+                spectra.concat()
+            ),
+        );
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        for spectrum in &run.spectra {
+            assert!(spectrum.is_centroided());
+            assert_eq!(spectrum.polarity(), Some(Polarity::Positive));
+        }
+    }
+
+    #[test]
+    fn source_files_are_read_from_the_file_description() {
+        let header = format!(
+            r#"<fileDescription><fileContent/><sourceFileList count="1"><sourceFile id="RAW1" name="sample.raw" location="file:///data">{}</sourceFile></sourceFileList></fileDescription>"#,
+            cv_param(accession::SHA1, "0123456789abcdef0123456789abcdef01234567")
+        );
+        let xml = mzml_with_header(&header, "");
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let source_files = &run.file_description.unwrap().source_files;
+        assert_eq!(source_files.len(), 1);
+        assert_eq!(source_files[0].id, "RAW1");
+        assert_eq!(source_files[0].name, "sample.raw");
+        assert_eq!(source_files[0].location, "file:///data");
+        assert_eq!(
+            source_files[0].sha1(),
+            Some("0123456789abcdef0123456789abcdef01234567")
+        );
+    }
+
+    #[test]
+    fn instrument_components_keep_their_params() {
+        let header = r#"<instrumentConfigurationList count="1"><instrumentConfiguration id="IC1"><componentList count="2"><source order="1"/><analyzer order="2"><cvParam cvRef="MS" accession="MS:1000484" name="orbitrap" value=""/></analyzer></componentList></instrumentConfiguration></instrumentConfigurationList>"#;
+        let xml = mzml_with_header(header, "");
+
+        let document = crate::parse_mzml_document(&xml).unwrap();
+        let configuration = &document.instrument_configurations[0];
+        assert_eq!(configuration.id, "IC1");
+        let analyzer = &configuration.components[1];
+        assert_eq!(analyzer.component_type, ComponentType::Analyzer);
+        assert_eq!(analyzer.order, 2);
+        assert_eq!(&*analyzer.cv_params[0].accession, "MS:1000484");
+        assert_eq!(&*analyzer.cv_params[0].name, "orbitrap");
+    }
+
+    #[test]
+    fn software_version_and_processing_are_read() {
+        let header = format!(
+            r#"<softwareList count="1"><software id="pwiz" version="3.0.20066"/></softwareList><dataProcessingList count="1"><dataProcessing id="conversion"><processingMethod order="0" softwareRef="pwiz">{}</processingMethod></dataProcessing></dataProcessingList>"#,
+            cv_param(accession::CONVERSION_TO_MZML, "")
+        );
+        let xml = mzml_with_header(&header, "");
+
+        let document = crate::parse_mzml_document(&xml).unwrap();
+        assert_eq!(document.software[0].id, "pwiz");
+        assert_eq!(document.software[0].version, "3.0.20066");
+        let method = &document.data_processing[0].processing_methods[0];
+        assert_eq!(method.software_ref, "pwiz");
+        assert_eq!(
+            &*method.cv_params[0].accession,
+            accession::CONVERSION_TO_MZML
+        );
+    }
+
+    #[test]
+    fn spectrum_refs_link_to_document_entries() {
+        let header = r#"<fileDescription><fileContent/><sourceFileList count="2"><sourceFile id="RAW1" name="a.raw" location="file:///data"/><sourceFile id="RAW2" name="b.raw" location="file:///data"/></sourceFileList></fileDescription>"#;
+        let tagged = spectrum(1, 1, &[(100.0, 1.0)], "").replacen(
+            "<spectrum ",
+            r#"<spectrum sourceFileRef="RAW2" dataProcessingRef="recalibration" "#,
+            1,
+        );
+        let xml = mzml_with_header(
+            header,
+            &format!(
+                "<spectrumList count=\"2\">{}{tagged}</spectrumList>",
+                spectrum(0, 1, &[(100.0, 1.0)], "")
+            ),
+        );
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        assert_eq!(run.spectra[0].source_file_ref, None);
+        assert_eq!(run.spectra[0].data_processing_ref, None);
+        assert_eq!(run.spectra[1].source_file_ref.as_deref(), Some("RAW2"));
+        assert_eq!(
+            run.spectra[1].data_processing_ref.as_deref(),
+            Some("recalibration")
+        );
+        let source_files = &run.file_description.unwrap().source_files;
+        let source = source_files
+            .iter()
+            .find(|file| Some(&file.id) == run.spectra[1].source_file_ref.as_ref())
+            .unwrap();
+        assert_eq!(source.name, "b.raw");
+    }
+
+    #[test]
+    fn repeated_terms_share_one_allocation() {
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 1.0)], ""),
+            spectrum(1, 1, &[(200.0, 2.0)], ""),
+        ]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        let first = &run.spectra[0].binary_data_arrays[0].cv_params[0];
+        let second = &run.spectra[1].binary_data_arrays[0].cv_params[0];
+        assert_eq!(&*first.accession, accession::MZ_ARRAY);
+        assert!(std::sync::Arc::ptr_eq(&first.accession, &second.accession));
+        assert!(std::sync::Arc::ptr_eq(&first.name, &second.name));
+        assert!(std::sync::Arc::ptr_eq(&first.cv_ref, &second.cv_ref));
+    }
+
+    #[test]
+    fn self_closing_elements_are_captured() {
+        let xml = mzml_with_spectra(&[
+            r#"<spectrum index="0" id="scan=1" defaultArrayLength="0"/>"#.to_string(),
+            format!(
+                r#"<spectrum index="1" id="scan=2" defaultArrayLength="0">{}{}<binaryDataArrayList count="1"><binaryDataArray encodedLength="0">{}<binary/></binaryDataArray></binaryDataArrayList></spectrum>"#,
+                cv_param(accession::MS_LEVEL, "2"),
+                cv_param(accession::MSN_SPECTRUM, ""),
+                cv_param(accession::MZ_ARRAY, ""),
+            ),
+        ]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        assert_eq!(run.spectra.len(), 2);
+        assert_eq!(run.spectra[0].id, "scan=1");
+        assert!(run.spectra[0].cv_params.is_empty());
+        let spectrum = &run.spectra[1];
+        assert_eq!(spectrum.cv_params.len(), 2);
+        assert_eq!(spectrum.ms_level(), Some(2));
+        assert_eq!(spectrum.binary_data_arrays.len(), 1);
+        assert_eq!(
+            &*spectrum.binary_data_arrays[0].cv_params[0].accession,
+            accession::MZ_ARRAY
+        );
+    }
+
+    #[test]
+    fn unknown_accessions_are_collected_once() {
+        let made_up =
+            r#"<cvParam cvRef="MS" accession="MS:9999999" name="made-up term" value=""/>"#;
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 1.0)], made_up),
+            spectrum(1, 1, &[(100.0, 1.0)], made_up),
+        ]);
+
+        let options = crate::options::ParseOptions::builder()
+            .collect_unknown_accessions(true)
+            .build();
+        let document =
+            crate::parse_mzml_document_reader_with_options(xml.as_bytes(), &options).unwrap();
+        assert_eq!(document.unknown_accessions.len(), 1);
+        assert!(document.unknown_accessions.contains("MS:9999999"));
+        // The param is still kept on the spectrum
+        assert_eq!(
+            &*document.run.spectra[1].cv_params[2].accession,
+            "MS:9999999"
+        );
+
+        let document = crate::parse_mzml_document(&xml).unwrap();
+        assert!(document.unknown_accessions.is_empty());
+    }
+
+    #[test]
+    fn mrm_chromatogram_keeps_its_transition() {
+        let run = crate::parse_mzml(&mzml(&mrm_chromatogram())).unwrap();
+
+        let chromatogram = &run.chromatograms[0];
+        let precursor = chromatogram.precursors[0]
+            .isolation_window_bounds()
+            .unwrap();
+        assert_eq!(precursor.target_mz, Some(500.3));
+        assert_eq!(
+            &*chromatogram.precursors[0].activation.cv_params[0].accession,
+            accession::CID
+        );
+        let [product] = &chromatogram.products[..] else {
+            panic!("expected one product");
+        };
+        assert_eq!(product.isolation_window.target_mz, Some(300.2));
+        assert_eq!(product.isolation_window.lower_bound(), Some(299.7));
+        assert_eq!(product.isolation_window.upper_bound(), Some(300.7));
+        assert_eq!(
+            chromatogram.intensity_array(),
+            Some(&[10.0, 50.0, 20.0][..])
+        );
+    }
+
+    #[test]
+    fn blank_index_names_the_attribute_and_spectrum() {
+        let xml = mzml_with_spectra(&[spectrum(6, 1, &[(100.0, 1.0)], "").replacen(
+            r#"index="6""#,
+            r#"index="""#,
+            1,
+        )]);
+
+        let err = crate::parse_mzml(&xml).unwrap_err();
+        assert!(err.position().is_some());
+        assert!(
+            err.to_string()
+                .starts_with(r#"Spectrum scan=7: Invalid value for attribute index: """#),
+            "{err}"
+        );
+        let MzmlError::AtPosition { error, .. } = err else {
+            panic!("expected a positioned error");
+        };
+        assert!(
+            matches!(*error, MzmlError::InSpectrum { spectrum_id, .. } if spectrum_id == "scan=7")
+        );
+    }
+
+    #[test]
+    fn duplicate_attributes_keep_the_first_value() {
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 1.0)], "").replacen(
+            r#"id="scan=1""#,
+            r#"id="first" id="second""#,
+            1,
+        )]);
+
+        let run = crate::parse_mzml(&xml).unwrap();
+        assert_eq!(run.spectra[0].id, "first");
+    }
+
+    #[test]
+    fn count_mismatches_fail_strict_and_are_recorded_lenient() {
+        let scan_list = r#"<scanList count="2"><scan/></scanList>"#;
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 1.0)], scan_list),
+            spectrum(1, 1, &[(100.0, 1.0)], ""),
+        ])
+        .replacen(
+            r#"<spectrumList count="2">"#,
+            r#"<spectrumList count="3">"#,
+            1,
+        );
+        let parse = |validation| {
+            let options = ParseOptions::builder().count_validation(validation).build();
+            crate::parse_mzml_document_reader_with_options(xml.as_bytes(), &options)
+        };
+
+        let err = parse(CountValidation::Strict).unwrap_err();
+        let MzmlError::AtPosition { error, .. } = err else {
+            panic!("expected a positioned error");
+        };
+        assert!(matches!(
+            *error,
+            MzmlError::CountMismatch(ref mismatch)
+                if mismatch.element == "scanList" && mismatch.declared == 2 && mismatch.actual == 1
+        ));
+
+        let document = parse(CountValidation::Lenient).unwrap();
+        assert_eq!(document.run.spectra.len(), 2);
+        let mismatches: Vec<(&str, usize, usize)> = document
+            .count_mismatches
+            .iter()
+            .map(|m| (m.element.as_str(), m.declared, m.actual))
+            .collect();
+        assert_eq!(mismatches, [("scanList", 2, 1), ("spectrumList", 3, 2)]);
+
+        assert!(parse(CountValidation::Off)
+            .unwrap()
+            .count_mismatches
+            .is_empty());
+    }
+
+    #[test]
+    fn sample_entry_is_read_and_linked_to_the_run() {
+        let header = r#"<sampleList count="1"><sample id="S1" name="plate 3 well A1"><cvParam cvRef="MS" accession="MS:1000002" name="sample name" value="A1"/></sample></sampleList>"#;
+        let xml = mzml_with_header(header, "").replacen(
+            r#"<run id="run""#,
+            r#"<run id="run" sampleRef="S1""#,
+            1,
+        );
+
+        let document = crate::parse_mzml_document(&xml).unwrap();
+        let [sample] = &document.samples[..] else {
+            panic!("expected one sample");
+        };
+        assert_eq!(sample.id, "S1");
+        assert_eq!(sample.name.as_deref(), Some("plate 3 well A1"));
+        assert_eq!(&*sample.cv_params[0].accession, "MS:1000002");
+        assert_eq!(document.run.sample_ref.as_deref(), Some("S1"));
+        assert_eq!(document.run_sample(), Some(sample));
+    }
+}
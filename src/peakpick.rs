@@ -0,0 +1,263 @@
+use crate::accession;
+use crate::models::{BinaryDataArray, CvParam, Spectrum, SpectrumCache};
+
+/// Picks local intensity maxima from a profile spectrum, returning a centroided copy
+///
+/// Each apex at or above min_intensity is refined by fitting a parabola through it and its
+/// two neighbours. Arrays other than m/z and intensity cannot follow the picked peaks and
+/// are dropped. Spectra already flagged as centroided are returned unchanged.
+pub fn centroid(spectrum: &Spectrum, min_intensity: f64) -> Spectrum {
+    let mut centroided = spectrum.clone();
+    if spectrum.is_centroided() {
+        return centroided;
+    }
+
+    // Step 1: find the apexes in the profile arrays
+    let profile: Vec<(f64, f64)> = spectrum.peaks().collect();
+    let mut mz = Vec::new();
+    let mut intensity = Vec::new();
+    for window in profile.windows(3) {
+        let [left, apex, right] = [window[0], window[1], window[2]];
+        if apex.1 > left.1 && apex.1 >= right.1 && apex.1 >= min_intensity {
+            let (apex_mz, apex_intensity) = refine_apex(left, apex, right);
+            mz.push(apex_mz);
+            intensity.push(apex_intensity);
+        }
+    }
+
+    // Step 2: swap the picked peaks into the m/z and intensity arrays
+    replace_peaks(&mut centroided, mz, intensity);
+
+    // Step 3: flag the spectrum as centroided in place of any profile flag
+    let flag = ms_param(accession::CENTROID_SPECTRUM, "centroid spectrum");
+    match centroided
+        .cv_params
+        .iter_mut()
+        .find(|p| &*p.accession == accession::PROFILE_SPECTRUM)
+    {
+        Some(param) => *param = flag,
+        None => centroided.cv_params.push(flag),
+    }
+
+    centroided
+}
+
+/// Sums spectra by binning their peaks on m/z, e.g. to combine MS1 scans across a chromatographic peak
+///
+/// Peaks join the current bin while they are within mz_tolerance of its mean m/z. Each bin
+/// becomes one peak with the mean m/z and the summed intensity of its members. The result
+/// keeps the metadata of the first spectrum.
+pub fn sum_spectra(spectra: &[&Spectrum], mz_tolerance: f64) -> Spectrum {
+    let mut summed = match spectra.first() {
+        Some(first) => (*first).clone(),
+        None => Spectrum {
+            id: String::new(),
+            index: 0,
+            default_array_length: 0,
+            source_file_ref: None,
+            data_processing_ref: None,
+            cv_params: Vec::new(),
+            user_params: Vec::new(),
+            scan_list: None,
+            precursors: Vec::new(),
+            binary_data_arrays: Vec::new(),
+            cache: SpectrumCache::default(),
+        },
+    };
+
+    // Step 1: pool the peaks of every spectrum in m/z order
+    let mut peaks: Vec<(f64, f64)> = spectra.iter().flat_map(|s| s.peaks()).collect();
+    peaks.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    // Step 2: grow each bin until the next peak is further than the tolerance from its mean
+    let mut mz = Vec::new();
+    let mut intensity = Vec::new();
+    let mut bin: Option<(f64, usize, f64)> = None; // (m/z sum, count, intensity sum)
+    for (peak_mz, peak_intensity) in peaks {
+        match bin.as_mut() {
+            Some((mz_sum, count, intensity_sum))
+                if peak_mz - *mz_sum / *count as f64 <= mz_tolerance =>
+            {
+                *mz_sum += peak_mz;
+                *count += 1;
+                *intensity_sum += peak_intensity;
+            }
+            _ => {
+                if let Some((mz_sum, count, intensity_sum)) = bin {
+                    mz.push(mz_sum / count as f64);
+                    intensity.push(intensity_sum);
+                }
+                bin = Some((peak_mz, 1, peak_intensity));
+            }
+        }
+    }
+    if let Some((mz_sum, count, intensity_sum)) = bin {
+        mz.push(mz_sum / count as f64);
+        intensity.push(intensity_sum);
+    }
+
+    replace_peaks(&mut summed, mz, intensity);
+    summed
+}
+
+/// Returns the cosine of the angle between two binned spectra, as made by Spectrum::binned
+///
+/// Vectors of different lengths are compared over the shorter one. A vector with no
+/// intensity has no direction, so comparing it gives 0.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f64, 0.0f64, 0.0f64);
+    for (&x, &y) in a.iter().zip(b) {
+        let (x, y) = (f64::from(x), f64::from(y));
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    (dot / (norm_a.sqrt() * norm_b.sqrt())) as f32
+}
+
+/// Replaces a spectrum's arrays with decoded m/z and intensity arrays, keeping their cvParams
+pub(crate) fn replace_peaks(spectrum: &mut Spectrum, mz: Vec<f64>, intensity: Vec<f64>) {
+    let mut mz_array = take_array(spectrum, accession::MZ_ARRAY, "m/z array");
+    let mut intensity_array = take_array(spectrum, accession::INTENSITY_ARRAY, "intensity array");
+    spectrum.default_array_length = mz.len();
+    mz_array.decoded_data = Some(mz);
+    intensity_array.decoded_data = Some(intensity);
+    spectrum.binary_data_arrays = vec![mz_array, intensity_array];
+}
+
+/// Takes the array tagged with the given accession, or creates an empty 64-bit float one
+pub(crate) fn take_array(
+    spectrum: &mut Spectrum,
+    array_accession: &str,
+    name: &str,
+) -> BinaryDataArray {
+    let position = spectrum
+        .binary_data_arrays
+        .iter()
+        .position(|a| a.cv_params.iter().any(|p| &*p.accession == array_accession));
+    let mut array = match position {
+        Some(position) => spectrum.binary_data_arrays.swap_remove(position),
+        None => BinaryDataArray {
+            encoded_length: 0,
+            cv_params: vec![
+                ms_param(array_accession, name),
+                ms_param(accession::FLOAT_64_BIT, "64-bit float"),
+                ms_param(accession::NO_COMPRESSION, "no compression"),
+            ],
+            encoded_data: None,
+            decoded_data: None,
+            raw_binary: None,
+        },
+    };
+    array.encoded_length = 0;
+    array.encoded_data = None;
+    array.raw_binary = None;
+    array
+}
+
+pub(crate) fn ms_param(accession: &str, name: &str) -> CvParam {
+    CvParam {
+        cv_ref: "MS".into(),
+        accession: accession.into(),
+        name: name.into(),
+        value: None,
+        unit_name: None,
+        unit_accession: None,
+        unit_cv_ref: None,
+    }
+}
+
+/// Returns the vertex of the parabola through three points, or the middle point if it is not concave
+fn refine_apex(left: (f64, f64), apex: (f64, f64), right: (f64, f64)) -> (f64, f64) {
+    // Work relative to the apex so the fit stays well conditioned at large m/z
+    let (d0, r0) = (left.0 - apex.0, left.1 - apex.1);
+    let (d2, r2) = (right.0 - apex.0, right.1 - apex.1);
+    let det = d0 * d2 * (d2 - d0);
+    if det == 0.0 {
+        return apex;
+    }
+
+    let a = (d0 * r2 - d2 * r0) / det;
+    let b = (r0 * d2 * d2 - r2 * d0 * d0) / det;
+    if a >= 0.0 {
+        return apex;
+    }
+
+    let offset = -b / (2.0 * a);
+    if offset < d0 || offset > d2 {
+        return apex;
+    }
+    (apex.0 + offset, apex.1 - b * b / (4.0 * a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn centroids_a_gaussian_profile_peak() {
+        let (center, sigma) = (500.0215, 0.01);
+        let mz: Vec<f64> = (0..40).map(|i| 499.9 + i as f64 * 0.005).collect();
+        let intensity = mz
+            .iter()
+            .map(|m| 1000.0 * (-((m - center) / sigma).powi(2) / 2.0).exp())
+            .collect();
+        let profile = Spectrum::builder()
+            .peaks(mz, intensity)
+            .cv_param(ms_param(accession::PROFILE_SPECTRUM, "profile spectrum"))
+            .build();
+
+        let centroided = centroid(&profile, 1.0);
+        assert!(centroided.is_centroided());
+        assert!(!centroided.is_profile());
+        let peaks: Vec<_> = centroided.peaks().collect();
+        assert_eq!(peaks.len(), 1);
+        assert!((peaks[0].0 - center).abs() < 1e-3);
+        assert!((peaks[0].1 - 1000.0).abs() < 50.0);
+    }
+
+    #[test]
+    fn sums_overlapping_and_distinct_peaks() {
+        let a = Spectrum::builder()
+            .peaks(vec![100.0, 200.0], vec![10.0, 20.0])
+            .build();
+        let b = Spectrum::builder()
+            .peaks(vec![100.002, 300.0], vec![30.0, 5.0])
+            .build();
+
+        let summed = sum_spectra(&[&a, &b], 0.01);
+        let peaks: Vec<_> = summed.peaks().collect();
+        assert_eq!(peaks.len(), 3);
+        assert!((peaks[0].0 - 100.001).abs() < 1e-9);
+        assert_eq!(peaks[0].1, 40.0);
+        assert_eq!(peaks[1], (200.0, 20.0));
+        assert_eq!(peaks[2], (300.0, 5.0));
+        assert_eq!(summed.default_array_length, 3);
+    }
+
+    #[test]
+    fn binned_spectrum_is_identical_to_itself() {
+        let spectrum = Spectrum::builder()
+            .peaks(
+                vec![101.2, 101.6, 250.0, 512.3, 999.9],
+                vec![10.0, 5.0, 200.0, 30.0, 1.0],
+            )
+            .build();
+
+        let bins = spectrum.binned(1.0, 1000.0);
+        assert_eq!(bins.len(), 1000);
+        // The two peaks at 101.x share a bin
+        assert_eq!(bins[101], 15.0);
+        assert!((cosine_similarity(&bins, &bins) - 1.0).abs() < 1e-6);
+
+        let other = Spectrum::builder()
+            .peaks(vec![300.0], vec![50.0])
+            .build()
+            .binned(1.0, 1000.0);
+        assert_eq!(cosine_similarity(&bins, &other), 0.0);
+        assert_eq!(cosine_similarity(&bins, &vec![0.0; 1000]), 0.0);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::accession;
+use crate::cv;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Wraps run content, e.g. a <spectrumList>, in the smallest document the parser accepts
+pub(crate) fn mzml(run_content: &str) -> String {
+    mzml_with_header("", run_content)
+}
+
+/// Wraps header sections, e.g. a <referenceableParamGroupList>, and run content in a document
+pub(crate) fn mzml_with_header(header: &str, run_content: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<mzML xmlns="http://psi.hupo.org/ms/mzml" version="1.1.0">
+{header}
+<run id="run" startTimeStamp="2024-01-01T00:00:00Z">
+{run_content}
+</run>
+</mzML>"#
+    )
+}
+
+/// Wraps spectrum elements in a <spectrumList> inside a minimal document
+pub(crate) fn mzml_with_spectra(spectra: &[String]) -> String {
+    mzml(&format!(
+        "<spectrumList count=\"{}\">\n{}\n</spectrumList>",
+        spectra.len(),
+        spectra.concat()
+    ))
+}
+
+/// Returns a <cvParam> for a term in the cv table, e.g. cv_param(accession::MS_LEVEL, "2")
+pub(crate) fn cv_param(term: &str, value: &str) -> String {
+    let cv_ref = term.split(':').next().unwrap_or("MS");
+    let name = cv::lookup(term).unwrap_or("unknown term");
+    format!(r#"<cvParam cvRef="{cv_ref}" accession="{term}" name="{name}" value="{value}"/>"#)
+}
+
+/// Returns a <cvParam> whose value carries a UO unit, e.g. a scan start time in minutes
+pub(crate) fn cv_param_with_unit(term: &str, value: &str, unit: &str) -> String {
+    let name = cv::lookup(term).unwrap_or("unknown term");
+    let unit_name = cv::lookup(unit).unwrap_or("unknown unit");
+    format!(
+        r#"<cvParam cvRef="MS" accession="{term}" name="{name}" value="{value}" unitCvRef="UO" unitAccession="{unit}" unitName="{unit_name}"/>"#
+    )
+}
+
+/// Returns a <binaryDataArray> holding the base64 text under params for the given terms
+pub(crate) fn binary_array(terms: &[&str], binary: &str) -> String {
+    let params: String = terms.iter().map(|term| cv_param(term, "")).collect();
+    format!(
+        r#"<binaryDataArray encodedLength="{}">{params}<binary>{binary}</binary></binaryDataArray>"#,
+        binary.len()
+    )
+}
+
+/// Returns base64 of little-endian 64-bit floats
+pub(crate) fn f64_base64(values: &[f64]) -> String {
+    let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    STANDARD.encode(bytes)
+}
+
+/// Returns base64 of little-endian 32-bit floats
+pub(crate) fn f32_base64(values: &[f32]) -> String {
+    let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    STANDARD.encode(bytes)
+}
+
+/// Returns a spectrum with uncompressed 64-bit m/z and intensity arrays
+///
+/// Its id is "scan=N" for index N - 1, and extra holds any elements to add before the
+/// arrays, e.g. a <scanList>.
+pub(crate) fn spectrum(index: usize, ms_level: u8, peaks: &[(f64, f64)], extra: &str) -> String {
+    let spectrum_type = match ms_level {
+        1 => accession::MS1_SPECTRUM,
+        _ => accession::MSN_SPECTRUM,
+    };
+    let (mz, intensity): (Vec<f64>, Vec<f64>) = peaks.iter().copied().unzip();
+    let arrays = [
+        binary_array(
+            &[
+                accession::MZ_ARRAY,
+                accession::FLOAT_64_BIT,
+                accession::NO_COMPRESSION,
+            ],
+            &f64_base64(&mz),
+        ),
+        binary_array(
+            &[
+                accession::INTENSITY_ARRAY,
+                accession::FLOAT_64_BIT,
+                accession::NO_COMPRESSION,
+            ],
+            &f64_base64(&intensity),
+        ),
+    ];
+    format!(
+        r#"<spectrum index="{index}" id="scan={}" defaultArrayLength="{}">{}{}{extra}<binaryDataArrayList count="2">{}</binaryDataArrayList></spectrum>
+"#,
+        index + 1,
+        peaks.len(),
+        cv_param(accession::MS_LEVEL, &ms_level.to_string()),
+        cv_param(spectrum_type, ""),
+        arrays.concat()
+    )
+}
+
+/// Writes contents to a file in the temp directory, unique to this process and name
+pub(crate) fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("libms-{}-{name}", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Wraps spectra in an indexedmzML document with a correct offset index and SHA-1 checksum
+pub(crate) fn indexed_mzml(spectra: &[String]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<indexedmzML xmlns=\"http://psi.hupo.org/ms/mzml\">\n{}\n",
+        mzml_with_spectra(spectra).lines().skip(1).collect::<Vec<_>>().join("\n")
+    );
+
+    // Step 1: index every <spectrum> start tag by the id that follows it
+    let mut offsets = String::new();
+    for (offset, _) in xml.match_indices("<spectrum ") {
+        let id_start = offset + xml[offset..].find("id=\"").unwrap() + 4;
+        let id_end = id_start + xml[id_start..].find('"').unwrap();
+        offsets.push_str(&format!(
+            "<offset idRef=\"{}\">{offset}</offset>\n",
+            &xml[id_start..id_end]
+        ));
+    }
+    let index_list_offset = xml.len();
+    xml.push_str(&format!(
+        "<indexList count=\"1\">\n<index name=\"spectrum\">\n{offsets}</index>\n</indexList>\n<indexListOffset>{index_list_offset}</indexListOffset>\n<fileChecksum>"
+    ));
+
+    // Step 2: the checksum covers everything through the <fileChecksum> start tag
+    let digest = Sha1::digest(xml.as_bytes());
+    let checksum: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    xml.push_str(&checksum);
+    xml.push_str("</fileChecksum>\n</indexedmzML>\n");
+    xml
+}
+
+/// Returns the zlib-compressed bytes
+pub(crate) fn zlib(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Returns a <chromatogramList> with one SRM transition, 500.3 -> 300.2, and three points
+pub(crate) fn mrm_chromatogram() -> String {
+    let arrays = [
+        binary_array(
+            &[
+                accession::TIME_ARRAY,
+                accession::FLOAT_64_BIT,
+                accession::NO_COMPRESSION,
+            ],
+            &f64_base64(&[1.0, 2.0, 3.0]),
+        ),
+        binary_array(
+            &[
+                accession::INTENSITY_ARRAY,
+                accession::FLOAT_64_BIT,
+                accession::NO_COMPRESSION,
+            ],
+            &f64_base64(&[10.0, 50.0, 20.0]),
+        ),
+    ];
+    format!(
+        r#"<chromatogramList count="1"><chromatogram index="0" id="SRM SIC Q1=500.3 Q3=300.2" defaultArrayLength="3"><cvParam cvRef="MS" accession="MS:1001473" name="selected reaction monitoring chromatogram" value=""/><precursor><isolationWindow>{}</isolationWindow><activation>{}</activation></precursor><product><isolationWindow>{}{}{}</isolationWindow></product><binaryDataArrayList count="2">{}</binaryDataArrayList></chromatogram></chromatogramList>"#,
+        cv_param_with_unit(
+            accession::ISOLATION_WINDOW_TARGET_MZ,
+            "500.3",
+            accession::MZ
+        ),
+        cv_param(accession::CID, ""),
+        cv_param_with_unit(
+            accession::ISOLATION_WINDOW_TARGET_MZ,
+            "300.2",
+            accession::MZ
+        ),
+        cv_param_with_unit(
+            accession::ISOLATION_WINDOW_LOWER_OFFSET,
+            "0.5",
+            accession::MZ
+        ),
+        cv_param_with_unit(
+            accession::ISOLATION_WINDOW_UPPER_OFFSET,
+            "0.5",
+            accession::MZ
+        ),
+        arrays.concat()
+    )
+}
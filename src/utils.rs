@@ -1,58 +1,266 @@
-use anyhow::{Context, Result};
-use base64::decode;
+use crate::accession;
+use crate::error::{MzmlError, Result};
+use crate::models::{CvParam, CvParamRef, UserParam};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use flate2::read::ZlibDecoder;
 use numpress::low_level::decode_linear;
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::io::Read;
+use std::str::FromStr;
+use std::sync::Arc;
 
+/// Byte order of the values inside a binary data array
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    /// The order required by the mzML spec
+    #[default]
+    LittleEndian,
+    /// Emitted by some legacy converters
+    BigEndian,
+}
+
+/// Precision of the values inside a binary data array
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    Float32,
+    Float64,
+    /// Cast into the f64 output
+    Integer32,
+    /// Cast into the f64 output; values above 2^53 lose precision
+    Integer64,
+}
+
+impl Precision {
+    /// Returns the precision a cvParam accession names, e.g. MS:1000523 for 64-bit float
+    pub fn from_accession(accession: &str) -> Option<Self> {
+        match accession {
+            accession::FLOAT_32_BIT => Some(Precision::Float32),
+            accession::FLOAT_64_BIT => Some(Precision::Float64),
+            accession::INTEGER_32_BIT => Some(Precision::Integer32),
+            accession::INTEGER_64_BIT => Some(Precision::Integer64),
+            _ => None,
+        }
+    }
+
+    /// Returns the size of one encoded value in bytes
+    pub fn size(self) -> usize {
+        match self {
+            Precision::Float32 | Precision::Integer32 => 4,
+            Precision::Float64 | Precision::Integer64 => 8,
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = MzmlError;
+
+    /// Parses a precision term name, e.g. "64-bit float"
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "32-bit float" => Ok(Precision::Float32),
+            "64-bit float" => Ok(Precision::Float64),
+            "32-bit integer" => Ok(Precision::Integer32),
+            "64-bit integer" => Ok(Precision::Integer64),
+            _ => Err(MzmlError::UnknownPrecision(name.to_string())),
+        }
+    }
+}
+
+/// A compression applied to a binary data array
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Zlib,
+    NumpressLinear,
+    NumpressPic,
+    NumpressSlof,
+}
+
+impl Codec {
+    /// Returns the codecs a compression accession names, in the order to undo them
+    ///
+    /// The combined Numpress + zlib terms name two codecs; no compression names none. None
+    /// means the accession is not a compression term libms knows.
+    pub fn from_accession(accession: &str) -> Option<&'static [Codec]> {
+        match accession {
+            accession::NO_COMPRESSION => Some(&[]),
+            accession::ZLIB_COMPRESSION => Some(&[Codec::Zlib]),
+            accession::NUMPRESS_LINEAR => Some(&[Codec::NumpressLinear]),
+            accession::NUMPRESS_PIC => Some(&[Codec::NumpressPic]),
+            accession::NUMPRESS_SLOF => Some(&[Codec::NumpressSlof]),
+            accession::NUMPRESS_LINEAR_ZLIB => Some(&[Codec::Zlib, Codec::NumpressLinear]),
+            accession::NUMPRESS_PIC_ZLIB => Some(&[Codec::Zlib, Codec::NumpressPic]),
+            accession::NUMPRESS_SLOF_ZLIB => Some(&[Codec::Zlib, Codec::NumpressSlof]),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = MzmlError;
+
+    /// Parses a codec name, e.g. "zlib" or "MS-Numpress linear"
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "zlib" => Ok(Codec::Zlib),
+            "MS-Numpress linear" => Ok(Codec::NumpressLinear),
+            "MS-Numpress positive integer" => Ok(Codec::NumpressPic),
+            "MS-Numpress short logged float" => Ok(Codec::NumpressSlof),
+            _ => Err(MzmlError::UnknownCompression(name.to_string())),
+        }
+    }
+}
+
+/// Decodes a binary data array from codec and precision names, e.g. Some("zlib") and "64-bit float"
+///
+/// A compression of None or "no compression" leaves the data as is; any other name libms does
+/// not know is an error.
 pub fn decode_binary_data(
     encoded: &str,
     compression: Option<&str>,
     precision: &str,
-) -> Result<Vec<f32>> {
-    // Step 1: Base64 decode
-    let raw_data = decode(encoded).context("Failed to decode Base64")?;
-
-    // Step 2: Decompress (if needed)
-    let decompressed_data = match compression {
-        Some("zlib") => {
-            let mut decoder = ZlibDecoder::new(&raw_data[..]);
-            let mut decompressed = Vec::new();
-            decoder.read_to_end(&mut decompressed)?;
-            decompressed
-        }
-        Some("MS-Numpress linear") => decode_ms_numpress(&raw_data)?,
-        _ => raw_data, // No decompression needed
+) -> Result<Vec<f64>> {
+    decode_binary_data_with_byte_order(encoded, compression, precision, ByteOrder::LittleEndian)
+}
+
+/// Decodes a binary data array whose float values use the given byte order
+pub fn decode_binary_data_with_byte_order(
+    encoded: &str,
+    compression: Option<&str>,
+    precision: &str,
+    byte_order: ByteOrder,
+) -> Result<Vec<f64>> {
+    let codec = match compression {
+        None | Some("no compression") => None,
+        Some(name) => Some(name.parse::<Codec>()?),
     };
+    decode_binary_data_with_codecs(encoded, codec.as_slice(), precision.parse()?, byte_order)
+}
 
-    // Step 3: Convert to floats
+/// Decodes a binary data array, undoing each codec in the order given
+///
+/// A Numpress codec yields doubles directly, so it must come last, e.g. [Zlib, NumpressLinear]
+pub fn decode_binary_data_with_codecs(
+    encoded: &str,
+    codecs: &[Codec],
+    precision: Precision,
+    byte_order: ByteOrder,
+) -> Result<Vec<f64>> {
+    // An empty <binary/> is a zero-length array whatever codecs are declared
+    if encoded.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Step 1: Base64 decode, dropping any line breaks or indentation from pretty-printing
+    let mut decompressed_data = if encoded.bytes().any(|b| b.is_ascii_whitespace()) {
+        let stripped: Vec<u8> = encoded
+            .bytes()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect();
+        STANDARD.decode(stripped)?
+    } else {
+        STANDARD.decode(encoded)?
+    };
+
+    // Step 2: Decompress (if needed); Numpress codecs decode straight to doubles
+    for (i, &codec) in codecs.iter().enumerate() {
+        let numpress: fn(&[u8]) -> Result<Vec<f64>> = match codec {
+            Codec::Zlib => {
+                let mut decoder = ZlibDecoder::new(&decompressed_data[..]);
+                let mut decompressed = Vec::new();
+                decoder
+                    .read_to_end(&mut decompressed)
+                    .map_err(MzmlError::Decompression)?;
+                decompressed_data = decompressed;
+                continue;
+            }
+            Codec::NumpressLinear => decode_ms_numpress,
+            Codec::NumpressSlof => decode_ms_numpress_slof,
+            Codec::NumpressPic => decode_ms_numpress_pic,
+        };
+        if i + 1 != codecs.len() {
+            return Err(MzmlError::Numpress(format!(
+                "{:?} must be the last codec to undo",
+                codec
+            )));
+        }
+        return numpress(&decompressed_data);
+    }
+
+    // Step 3: Convert to f64, widening 32-bit values so 64-bit ones keep full precision
+    let big_endian = byte_order == ByteOrder::BigEndian;
     match precision {
-        "32-bit float" => Ok(decompressed_data
+        Precision::Float32 => Ok(decompressed_data
             .chunks_exact(4)
-            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .map(|chunk| {
+                let bytes = chunk.try_into().unwrap();
+                if big_endian {
+                    f32::from_be_bytes(bytes) as f64
+                } else {
+                    f32::from_le_bytes(bytes) as f64
+                }
+            })
             .collect()),
-        "64-bit float" => Ok(decompressed_data
+        Precision::Float64 => Ok(decompressed_data
             .chunks_exact(8)
-            .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()) as f32)
+            .map(|chunk| {
+                let bytes = chunk.try_into().unwrap();
+                if big_endian {
+                    f64::from_be_bytes(bytes)
+                } else {
+                    f64::from_le_bytes(bytes)
+                }
+            })
+            .collect()),
+        Precision::Integer32 => Ok(decompressed_data
+            .chunks_exact(4)
+            .map(|chunk| {
+                let bytes = chunk.try_into().unwrap();
+                if big_endian {
+                    i32::from_be_bytes(bytes) as f64
+                } else {
+                    i32::from_le_bytes(bytes) as f64
+                }
+            })
+            .collect()),
+        Precision::Integer64 => Ok(decompressed_data
+            .chunks_exact(8)
+            .map(|chunk| {
+                let bytes = chunk.try_into().unwrap();
+                if big_endian {
+                    i64::from_be_bytes(bytes) as f64
+                } else {
+                    i64::from_le_bytes(bytes) as f64
+                }
+            })
             .collect()),
-        _ => Err(anyhow::anyhow!("Unknown precision: {}", precision)),
     }
 }
 
+/// Encodes values as base64 of uncompressed little-endian 32-bit floats
+pub fn encode_binary_data(values: &[f64]) -> String {
+    let bytes: Vec<u8> = values
+        .iter()
+        .flat_map(|&v| (v as f32).to_le_bytes())
+        .collect();
+    STANDARD.encode(bytes)
+}
+
 /// Decodes MS-Numpress linear-compressed data
-fn decode_ms_numpress(data: &[u8]) -> Result<Vec<u8>> {
-    // Estimate maximum output size: (data.len() - 8) * 2
+fn decode_ms_numpress(data: &[u8]) -> Result<Vec<f64>> {
+    if data.len() < 8 {
+        return Err(MzmlError::NumpressTooShort(data.len()));
+    }
+
+    // decode_linear writes through a raw pointer, so the buffer must fit its worst case: after
+    // the two 4-byte seeds every value takes at least one half-byte, which (len - 8) * 2 covers
     let max_decoded_size = (data.len() - 8) * 2;
-    let mut decoded_data = Vec::with_capacity(max_decoded_size);
+    let mut decoded_data: Vec<f64> = Vec::with_capacity(max_decoded_size);
 
     // Call unsafe decode_linear function
-    let decoded_count = unsafe {
-        decode_linear(
-            data.as_ptr(),
-            data.len(),
-            decoded_data.as_mut_ptr() as *mut f64,
-        )
-    }
-    .context("Failed to decode MS-Numpress linear")?;
+    let decoded_count =
+        unsafe { decode_linear(data.as_ptr(), data.len(), decoded_data.as_mut_ptr()) }
+            .map_err(|e| MzmlError::Numpress(e.to_string()))?;
 
     // Set the actual length of the decoded vector
     unsafe {
@@ -62,26 +270,395 @@ fn decode_ms_numpress(data: &[u8]) -> Result<Vec<u8>> {
     Ok(decoded_data)
 }
 
-pub fn get_attr(e: &quick_xml::events::BytesStart, attr_name: &str) -> Result<String> {
-    e.attributes()
-        .find_map(|a| {
-            let a = a.ok()?;
-            if a.key.as_ref() == attr_name.as_bytes() {
-                Some(String::from_utf8_lossy(&a.value).into_owned())
-            } else {
-                None
-            }
-        })
-        .context(format!("Missing attribute: {}", attr_name))
+/// Decodes MS-Numpress slof (short logged float) compressed data
+// usize::is_multiple_of needs Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn decode_ms_numpress_slof(data: &[u8]) -> Result<Vec<f64>> {
+    if data.len() < 8 {
+        return Err(MzmlError::NumpressTooShort(data.len()));
+    }
+    if data.len() % 2 != 0 {
+        return Err(MzmlError::Numpress("odd slof payload length".to_string()));
+    }
+
+    // The 8-byte header holds the fixed point as a big-endian double
+    let fixed_point = f64::from_be_bytes(data[..8].try_into().unwrap());
+
+    Ok(data[8..]
+        .chunks_exact(2)
+        .map(|chunk| (u16::from_le_bytes([chunk[0], chunk[1]]) as f64 / fixed_point).exp() - 1.0)
+        .collect())
 }
 
-pub fn get_attr_optional(e: &quick_xml::events::BytesStart, attr_name: &str) -> Option<String> {
+/// Decodes MS-Numpress pic (positive integer) compressed data
+fn decode_ms_numpress_pic(data: &[u8]) -> Result<Vec<f64>> {
+    // Each value takes at least one half-byte
+    let mut decoded_data = Vec::with_capacity(data.len() * 2);
+    let mut di = 0;
+    let mut half = false;
+
+    while di < data.len() {
+        // A trailing zero half-byte is padding, not another value
+        if di == data.len() - 1 && half && data[di] & 0xf == 0 {
+            break;
+        }
+        decoded_data.push(decode_numpress_int(data, &mut di, &mut half)? as f64);
+    }
+
+    Ok(decoded_data)
+}
+
+/// Decodes one half-byte encoded integer, the inverse of Numpress' encodeInt
+fn decode_numpress_int(data: &[u8], di: &mut usize, half: &mut bool) -> Result<u32> {
+    let head = read_half_byte(data, di, half)?;
+
+    // Heads above 8 mean the leading half-bytes are all ones
+    let n = if head <= 8 { head } else { head - 8 } as usize;
+    let mut value: u32 = if head <= 8 { 0 } else { !(u32::MAX >> (4 * n)) };
+
+    for i in n..8 {
+        value |= (read_half_byte(data, di, half)? as u32) << ((i - n) * 4);
+    }
+
+    Ok(value)
+}
+
+fn read_half_byte(data: &[u8], di: &mut usize, half: &mut bool) -> Result<u8> {
+    let byte = *data
+        .get(*di)
+        .ok_or_else(|| MzmlError::Numpress("truncated data".to_string()))?;
+    let half_byte = if *half {
+        *di += 1;
+        byte & 0xf
+    } else {
+        byte >> 4
+    };
+    *half = !*half;
+    Ok(half_byte)
+}
+
+/// Returns the raw value of an attribute, borrowed from the element where possible
+pub fn get_attr_bytes<'a>(
+    e: &'a quick_xml::events::BytesStart,
+    attr_name: &str,
+) -> Option<Cow<'a, [u8]>> {
     e.attributes().find_map(|a| {
         let a = a.ok()?;
-        if a.key.as_ref() == attr_name.as_bytes() {
-            Some(String::from_utf8_lossy(&a.value).into_owned())
-        } else {
-            None
+        (a.key.as_ref() == attr_name.as_bytes()).then_some(a.value)
+    })
+}
+
+pub fn get_attr(e: &quick_xml::events::BytesStart, attr_name: &str) -> Result<String> {
+    get_attr_optional(e, attr_name)
+        .ok_or_else(|| MzmlError::MissingAttribute(attr_name.to_string()))
+}
+
+/// Reads a required attribute and parses it, e.g. as a count or index
+pub fn get_attr_parsed<T: FromStr>(
+    e: &quick_xml::events::BytesStart,
+    attr_name: &str,
+) -> Result<T> {
+    let value = get_attr(e, attr_name)?;
+    value.parse().map_err(|_| MzmlError::InvalidAttribute {
+        name: attr_name.to_string(),
+        value,
+    })
+}
+
+pub fn get_attr_optional(e: &quick_xml::events::BytesStart, attr_name: &str) -> Option<String> {
+    get_attr_bytes(e, attr_name).map(|value| String::from_utf8_lossy(&value).into_owned())
+}
+
+/// Hands out one shared allocation per distinct string
+#[derive(Debug, Default)]
+pub(crate) struct StringPool(HashSet<Arc<str>>);
+
+impl StringPool {
+    /// Returns the pooled copy of the string, adding it on first use
+    pub(crate) fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(pooled) = self.0.get(s) {
+            return pooled.clone();
         }
+        let pooled: Arc<str> = s.into();
+        self.0.insert(pooled.clone());
+        pooled
+    }
+}
+
+/// Builds a CvParam from the attributes of a <cvParam> element
+pub fn parse_cv_param(e: &quick_xml::events::BytesStart) -> Result<CvParam> {
+    parse_cv_param_pooled(e, &mut StringPool::default())
+}
+
+/// Builds a CvParam, sharing its term strings with earlier params through the pool
+pub(crate) fn parse_cv_param_pooled(
+    e: &quick_xml::events::BytesStart,
+    pool: &mut StringPool,
+) -> Result<CvParam> {
+    let mut optional = |name| get_attr_optional(e, name).map(|value| pool.intern(&value));
+    let unit_name = optional("unitName");
+    let unit_accession = optional("unitAccession");
+    let unit_cv_ref = optional("unitCvRef");
+    Ok(CvParam {
+        cv_ref: pool.intern(&get_attr(e, "cvRef")?),
+        accession: pool.intern(&get_attr(e, "accession")?),
+        name: pool.intern(&get_attr(e, "name")?),
+        value: get_attr_optional(e, "value"),
+        unit_name,
+        unit_accession,
+        unit_cv_ref,
     })
 }
+
+/// Builds a CvParamRef from a <cvParam> element read directly out of source
+pub(crate) fn parse_cv_param_ref<'a>(
+    source: &'a str,
+    e: &quick_xml::events::BytesStart,
+) -> Result<CvParamRef<'a>> {
+    let mut cv_ref = None;
+    let mut accession = None;
+    let mut name = None;
+    let mut param = CvParamRef::default();
+    // The duplicate-attribute check keeps a list of keys, which would be the one allocation left
+    for attribute in e.attributes().with_checks(false).flatten() {
+        // Attribute values of a slice reader are subslices of source, so find them there
+        let Some(value) = subslice(source, &attribute.value) else {
+            continue;
+        };
+        match attribute.key.as_ref() {
+            b"cvRef" => cv_ref = Some(value),
+            b"accession" => accession = Some(value),
+            b"name" => name = Some(value),
+            b"value" => param.value = Some(value),
+            b"unitName" => param.unit_name = Some(value),
+            b"unitAccession" => param.unit_accession = Some(value),
+            b"unitCvRef" => param.unit_cv_ref = Some(value),
+            _ => {}
+        }
+    }
+    let missing = |name: &str| MzmlError::MissingAttribute(name.to_string());
+    param.cv_ref = cv_ref.ok_or_else(|| missing("cvRef"))?;
+    param.accession = accession.ok_or_else(|| missing("accession"))?;
+    param.name = name.ok_or_else(|| missing("name"))?;
+    Ok(param)
+}
+
+/// Returns part as a &str borrowed from source, if it lies inside source
+fn subslice<'a>(source: &'a str, part: &[u8]) -> Option<&'a str> {
+    let start = (part.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+    source.get(start..start + part.len())
+}
+
+/// Builds a UserParam from the attributes of a <userParam> element
+pub fn parse_user_param(e: &quick_xml::events::BytesStart) -> Result<UserParam> {
+    Ok(UserParam {
+        name: get_attr(e, "name")?,
+        value: get_attr_optional(e, "value"),
+        type_: get_attr_optional(e, "type"),
+        unit_name: get_attr_optional(e, "unitName"),
+        unit_accession: get_attr_optional(e, "unitAccession"),
+        unit_cv_ref: get_attr_optional(e, "unitCvRef"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::BinaryDataArray;
+    use crate::peakpick::ms_param;
+    use crate::test_fixtures::f64_base64;
+
+    #[test]
+    fn decodes_64_bit_floats_without_rounding() {
+        let values = [0.1, 1234.567890123456, f64::MAX];
+        let decoded = decode_binary_data(&f64_base64(&values), None, "64-bit float").unwrap();
+        assert_eq!(decoded, values);
+    }
+
+    #[test]
+    fn short_numpress_input_is_an_error() {
+        assert!(matches!(
+            decode_ms_numpress(&[1, 2, 3]),
+            Err(MzmlError::NumpressTooShort(3))
+        ));
+    }
+
+    /// Encodes values the way Numpress' encodeSlof does, with the given fixed point
+    fn encode_slof(values: &[f64], fixed_point: f64) -> Vec<u8> {
+        let mut bytes = fixed_point.to_be_bytes().to_vec();
+        for value in values {
+            let stored = ((value + 1.0).ln() * fixed_point + 0.5) as u16;
+            bytes.extend(stored.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn decodes_slof_intensities() {
+        let values = [0.0, 1.0, 150.0, 12345.0];
+        let encoded = STANDARD.encode(encode_slof(&values, 5000.0));
+
+        let decoded = decode_binary_data(
+            &encoded,
+            Some("MS-Numpress short logged float"),
+            "32-bit float",
+        )
+        .unwrap();
+        assert_eq!(decoded.len(), values.len());
+        for (decoded, expected) in decoded.iter().zip(values) {
+            // One step of the 16-bit log scale is a relative error of about 1 / fixed_point
+            assert!((decoded - expected).abs() <= (expected + 1.0) * 2e-4);
+        }
+    }
+
+    #[test]
+    fn decodes_big_endian_floats() {
+        let bytes: Vec<u8> = [1.5f64, -2.25]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+
+        let decoded = decode_binary_data_with_byte_order(
+            &STANDARD.encode(bytes),
+            None,
+            "64-bit float",
+            ByteOrder::BigEndian,
+        )
+        .unwrap();
+        assert_eq!(decoded, [1.5, -2.25]);
+    }
+
+    #[test]
+    fn decodes_32_bit_integers() {
+        let bytes: Vec<u8> = [0i32, 7, -3, i32::MAX]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+
+        let decoded = decode_binary_data(&STANDARD.encode(bytes), None, "32-bit integer").unwrap();
+        assert_eq!(decoded, [0.0, 7.0, -3.0, i32::MAX as f64]);
+    }
+
+    #[test]
+    fn base64_with_line_breaks_decodes() {
+        let encoded = f64_base64(&[1.0, 2.0, 3.0]);
+        let wrapped = format!("{}\n    {}\r\n", &encoded[..10], &encoded[10..]);
+
+        let decoded = decode_binary_data(&wrapped, None, "64-bit float").unwrap();
+        assert_eq!(decoded, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn accession_constants_hold_their_terms() {
+        assert_eq!(accession::MZ_ARRAY, "MS:1000514");
+        assert_eq!(accession::ZLIB_COMPRESSION, "MS:1000574");
+        assert_eq!(accession::FLOAT_64_BIT, "MS:1000523");
+        assert_eq!(accession::MINUTE, "UO:0000031");
+    }
+
+    #[test]
+    fn codecs_and_precisions_come_from_accessions() {
+        assert_eq!(
+            Codec::from_accession(accession::NUMPRESS_PIC_ZLIB),
+            Some(&[Codec::Zlib, Codec::NumpressPic][..])
+        );
+        assert_eq!(
+            Codec::from_accession(accession::NO_COMPRESSION),
+            Some(&[][..])
+        );
+        assert_eq!(Codec::from_accession(accession::MZ_ARRAY), None);
+        assert_eq!(
+            Precision::from_accession(accession::INTEGER_64_BIT),
+            Some(Precision::Integer64)
+        );
+    }
+
+    #[test]
+    fn unknown_compression_is_an_error() {
+        let array = BinaryDataArray {
+            encoded_length: 4,
+            cv_params: vec![
+                ms_param(accession::FLOAT_32_BIT, "32-bit float"),
+                ms_param("MS:1003090", "truncation and zlib compression"),
+            ],
+            encoded_data: Some("AAAAAA==".to_string()),
+            decoded_data: None,
+            raw_binary: None,
+        };
+
+        assert!(matches!(
+            array.codecs(),
+            Err(MzmlError::UnknownCompression(_))
+        ));
+        assert!(matches!(
+            decode_binary_data("AAAAAA==", Some("lz4"), "32-bit float"),
+            Err(MzmlError::UnknownCompression(_))
+        ));
+    }
+
+    #[test]
+    fn attribute_helpers_read_raw_and_parsed_values() {
+        let e = quick_xml::events::BytesStart::from_content(
+            r#"spectrum index="7" id="scan=8" defaultArrayLength="many""#,
+            8,
+        );
+
+        assert_eq!(get_attr_bytes(&e, "id").as_deref(), Some(&b"scan=8"[..]));
+        assert!(get_attr_bytes(&e, "sourceFileRef").is_none());
+        assert_eq!(get_attr_parsed::<usize>(&e, "index").unwrap(), 7);
+
+        let err = get_attr_parsed::<usize>(&e, "defaultArrayLength").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"Invalid value for attribute defaultArrayLength: "many""#
+        );
+        let err = get_attr_parsed::<usize>(&e, "encodedLength").unwrap_err();
+        assert!(matches!(err, MzmlError::MissingAttribute(name) if name == "encodedLength"));
+    }
+
+    #[test]
+    fn unparseable_attribute_names_the_attribute_and_spectrum() {
+        let xml = crate::test_fixtures::mzml_with_spectra(&[
+            r#"<spectrum index="0" id="scan=1" defaultArrayLength="-2"/>"#.to_string(),
+        ]);
+
+        let err = crate::parse_mzml(&xml).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("defaultArrayLength"), "{message}");
+        assert!(message.contains("\"-2\""), "{message}");
+    }
+
+    #[test]
+    fn linear_decode_fits_the_densest_encoding() {
+        // Evenly spaced values leave every residual zero, one half-byte per value after the seeds
+        let values: Vec<f64> = (0..1001).map(|i| 100.0 + i as f64 * 0.5).collect();
+        let encoded = numpress::numpress_compress(&values, 1000.0).unwrap();
+        assert!(encoded.len() < 16 + values.len() / 2 + 2);
+
+        let decoded = decode_ms_numpress(&encoded).unwrap();
+        assert_eq!(decoded.len(), values.len());
+        assert!(decoded.len() <= (encoded.len() - 8) * 2);
+        for (decoded, expected) in decoded.iter().zip(&values) {
+            assert!((decoded - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn slof_decode_takes_two_bytes_per_value() {
+        let encoded = encode_slof(&[1.0; 5], 5000.0);
+        assert_eq!(decode_ms_numpress_slof(&encoded).unwrap().len(), 5);
+        assert_eq!(decode_ms_numpress_slof(&encoded[..8]).unwrap().len(), 0);
+        assert!(decode_ms_numpress_slof(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn pic_decode_handles_one_half_byte_per_value() {
+        // A zero is a lone head half-byte of 8, the most values a byte can hold
+        assert_eq!(decode_ms_numpress_pic(&[0x88; 4]).unwrap(), [0.0; 8]);
+        // 5 is head 7 then one half-byte; the trailing zero half-byte is padding
+        assert_eq!(decode_ms_numpress_pic(&[0x75]).unwrap(), [5.0]);
+        assert_eq!(decode_ms_numpress_pic(&[0x75, 0x80]).unwrap(), [5.0, 0.0]);
+        // A head promising more half-bytes than remain is an error, not an overread
+        assert!(decode_ms_numpress_pic(&[0x07]).is_err());
+    }
+}
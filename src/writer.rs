@@ -0,0 +1,679 @@
+use crate::accession;
+use crate::error::Result;
+use crate::models::{
+    BinaryDataArray, Chromatogram, CvParam, FileDescription, Precursor, Product, Run, ScanList,
+    Spectrum, UserParam,
+};
+use crate::utils::encode_binary_data;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::Writer;
+use std::io::Write;
+
+/// Precision and compression accessions replaced on every re-encoded array
+const ENCODING_ACCESSIONS: [&str; 12] = [
+    accession::INTEGER_32_BIT,
+    accession::FLOAT_32_BIT,
+    accession::INTEGER_64_BIT,
+    accession::FLOAT_64_BIT,
+    accession::ZLIB_COMPRESSION,
+    accession::NO_COMPRESSION,
+    accession::NUMPRESS_LINEAR,
+    accession::NUMPRESS_PIC,
+    accession::NUMPRESS_SLOF,
+    accession::NUMPRESS_LINEAR_ZLIB,
+    accession::NUMPRESS_PIC_ZLIB,
+    accession::NUMPRESS_SLOF_ZLIB,
+];
+
+/// Writes a Run as an mzML document, re-encoding arrays as uncompressed 32-bit floats
+///
+/// Arrays that kept their raw <binary> text, or were never decoded, are written verbatim with
+/// their original cvParams.
+pub fn write_mzml<W: Write>(run: &Run, writer: W) -> Result<()> {
+    let mut writer = Writer::new_with_indent(writer, b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("utf-8"), None)))?;
+
+    start(
+        &mut writer,
+        "mzML",
+        &[
+            ("xmlns", "http://psi.hupo.org/ms/mzml"),
+            ("version", "1.1.0"),
+        ],
+    )?;
+    write_document_header(&mut writer, run.file_description.as_ref())?;
+
+    start(
+        &mut writer,
+        "run",
+        &[
+            ("id", &run.id),
+            ("defaultInstrumentConfigurationRef", "IC"),
+            ("startTimeStamp", &run.start_time),
+        ],
+    )?;
+
+    let count = run.spectra.len().to_string();
+    start(
+        &mut writer,
+        "spectrumList",
+        &[
+            ("count", &count),
+            ("defaultDataProcessingRef", "libms_processing"),
+        ],
+    )?;
+    for spectrum in &run.spectra {
+        write_spectrum(&mut writer, spectrum)?;
+    }
+    end(&mut writer, "spectrumList")?;
+
+    if !run.chromatograms.is_empty() {
+        let count = run.chromatograms.len().to_string();
+        start(
+            &mut writer,
+            "chromatogramList",
+            &[
+                ("count", &count),
+                ("defaultDataProcessingRef", "libms_processing"),
+            ],
+        )?;
+        for chromatogram in &run.chromatograms {
+            write_chromatogram(&mut writer, chromatogram)?;
+        }
+        end(&mut writer, "chromatogramList")?;
+    }
+
+    end(&mut writer, "run")?;
+    end(&mut writer, "mzML")?;
+    Ok(())
+}
+
+/// Writes every peak as a `spectrum_id,mz,intensity` CSV row, skipping spectra without arrays
+pub fn write_spectra_csv<W: Write>(run: &Run, mut writer: W) -> Result<()> {
+    writeln!(writer, "spectrum_id,mz,intensity")?;
+    for spectrum in &run.spectra {
+        let id = csv_field(&spectrum.id);
+        for (mz, intensity) in spectrum.peaks() {
+            writeln!(writer, "{},{},{}", id, mz, intensity)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes every MS2 spectrum as an MGF BEGIN IONS/END IONS block
+pub fn write_mgf<W: Write>(run: &Run, mut writer: W) -> Result<()> {
+    for spectrum in run.spectra.iter().filter(|s| s.ms_level() == Some(2)) {
+        writeln!(writer, "BEGIN IONS")?;
+        writeln!(writer, "TITLE={}", spectrum.id)?;
+        if let Some(mz) = spectrum.precursor_mz() {
+            match spectrum.precursor_intensity() {
+                Some(intensity) => writeln!(writer, "PEPMASS={} {}", mz, intensity)?,
+                None => writeln!(writer, "PEPMASS={}", mz)?,
+            }
+        }
+        if let Some(charge) = spectrum.precursor_charge() {
+            let sign = if charge < 0 { '-' } else { '+' };
+            writeln!(writer, "CHARGE={}{}", charge.abs(), sign)?;
+        }
+        if let Some(retention_time) = spectrum.retention_time() {
+            writeln!(writer, "RTINSECONDS={}", retention_time)?;
+        }
+        for (mz, intensity) in spectrum.peaks() {
+            writeln!(writer, "{} {}", mz, intensity)?;
+        }
+        writeln!(writer, "END IONS")?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Quotes a CSV field if it contains a delimiter, quote or newline
+fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+/// Writes the document-level sections the mzML schema requires before <run>
+fn write_document_header<W: Write>(
+    writer: &mut Writer<W>,
+    file_description: Option<&FileDescription>,
+) -> Result<()> {
+    start(writer, "cvList", &[("count", "2")])?;
+    empty(
+        writer,
+        "cv",
+        &[
+            ("id", "MS"),
+            (
+                "fullName",
+                "Proteomics Standards Initiative Mass Spectrometry Ontology",
+            ),
+            (
+                "URI",
+                "https://raw.githubusercontent.com/HUPO-PSI/psi-ms-CV/master/psi-ms.obo",
+            ),
+        ],
+    )?;
+    empty(
+        writer,
+        "cv",
+        &[
+            ("id", "UO"),
+            ("fullName", "Unit Ontology"),
+            ("URI", "https://raw.githubusercontent.com/bio-ontology-research-group/unit-ontology/master/unit.obo"),
+        ],
+    )?;
+    end(writer, "cvList")?;
+
+    write_file_description(writer, file_description)?;
+
+    start(writer, "softwareList", &[("count", "1")])?;
+    start(
+        writer,
+        "software",
+        &[("id", "libms"), ("version", env!("CARGO_PKG_VERSION"))],
+    )?;
+    write_cv_param(
+        writer,
+        &cv_param(
+            accession::CUSTOM_UNRELEASED_SOFTWARE_TOOL,
+            "custom unreleased software tool",
+            Some("libms"),
+        ),
+    )?;
+    end(writer, "software")?;
+    end(writer, "softwareList")?;
+
+    start(writer, "instrumentConfigurationList", &[("count", "1")])?;
+    start(writer, "instrumentConfiguration", &[("id", "IC")])?;
+    write_cv_param(
+        writer,
+        &cv_param(accession::INSTRUMENT_MODEL, "instrument model", None),
+    )?;
+    end(writer, "instrumentConfiguration")?;
+    end(writer, "instrumentConfigurationList")?;
+
+    start(writer, "dataProcessingList", &[("count", "1")])?;
+    start(writer, "dataProcessing", &[("id", "libms_processing")])?;
+    start(
+        writer,
+        "processingMethod",
+        &[("order", "0"), ("softwareRef", "libms")],
+    )?;
+    write_cv_param(
+        writer,
+        &cv_param(accession::CONVERSION_TO_MZML, "Conversion to mzML", None),
+    )?;
+    end(writer, "processingMethod")?;
+    end(writer, "dataProcessing")?;
+    end(writer, "dataProcessingList")?;
+    Ok(())
+}
+
+fn write_spectrum<W: Write>(writer: &mut Writer<W>, spectrum: &Spectrum) -> Result<()> {
+    let index = spectrum.index.to_string();
+    let length = spectrum.default_array_length.to_string();
+    let mut attributes = vec![
+        ("index", index.as_str()),
+        ("id", spectrum.id.as_str()),
+        ("defaultArrayLength", length.as_str()),
+    ];
+    // dataProcessingRef is not written: the dataProcessingList is replaced on output
+    if let Some(source_file_ref) = &spectrum.source_file_ref {
+        attributes.push(("sourceFileRef", source_file_ref));
+    }
+    start(writer, "spectrum", &attributes)?;
+    write_cv_params(writer, &spectrum.cv_params)?;
+    write_user_params(writer, &spectrum.user_params)?;
+    if let Some(scan_list) = &spectrum.scan_list {
+        write_scan_list(writer, scan_list)?;
+    }
+    if !spectrum.precursors.is_empty() {
+        let count = spectrum.precursors.len().to_string();
+        start(writer, "precursorList", &[("count", &count)])?;
+        for precursor in &spectrum.precursors {
+            write_precursor(writer, precursor)?;
+        }
+        end(writer, "precursorList")?;
+    }
+    write_binary_data_arrays(writer, &spectrum.binary_data_arrays)?;
+    end(writer, "spectrum")
+}
+
+fn write_chromatogram<W: Write>(writer: &mut Writer<W>, chromatogram: &Chromatogram) -> Result<()> {
+    let index = chromatogram.index.to_string();
+    let length = chromatogram.default_array_length.to_string();
+    start(
+        writer,
+        "chromatogram",
+        &[
+            ("index", &index),
+            ("id", &chromatogram.id),
+            ("defaultArrayLength", &length),
+        ],
+    )?;
+    write_cv_params(writer, &chromatogram.cv_params)?;
+    // A chromatogram holds its precursor directly, without a <precursorList>
+    for precursor in &chromatogram.precursors {
+        write_precursor(writer, precursor)?;
+    }
+    for product in &chromatogram.products {
+        write_product(writer, product)?;
+    }
+    write_binary_data_arrays(writer, &chromatogram.binary_data_arrays)?;
+    end(writer, "chromatogram")
+}
+
+fn write_scan_list<W: Write>(writer: &mut Writer<W>, scan_list: &ScanList) -> Result<()> {
+    let count = scan_list.scans.len().to_string();
+    start(writer, "scanList", &[("count", &count)])?;
+    write_cv_params(writer, &scan_list.cv_params)?;
+    for scan in &scan_list.scans {
+        // instrumentConfigurationRef is not written: only a single "IC" configuration is declared
+        start(writer, "scan", &[])?;
+        write_cv_params(writer, &scan.cv_params)?;
+        write_user_params(writer, &scan.user_params)?;
+        if !scan.scan_windows.is_empty() {
+            let count = scan.scan_windows.len().to_string();
+            start(writer, "scanWindowList", &[("count", &count)])?;
+            for scan_window in &scan.scan_windows {
+                start(writer, "scanWindow", &[])?;
+                write_cv_params(writer, &scan_window.cv_params)?;
+                end(writer, "scanWindow")?;
+            }
+            end(writer, "scanWindowList")?;
+        }
+        end(writer, "scan")?;
+    }
+    end(writer, "scanList")
+}
+
+fn write_precursor<W: Write>(writer: &mut Writer<W>, precursor: &Precursor) -> Result<()> {
+    match &precursor.spectrum_ref {
+        Some(spectrum_ref) => start(writer, "precursor", &[("spectrumRef", spectrum_ref)])?,
+        None => start(writer, "precursor", &[])?,
+    }
+    if !precursor.isolation_window.is_empty() {
+        start(writer, "isolationWindow", &[])?;
+        write_cv_params(writer, &precursor.isolation_window)?;
+        end(writer, "isolationWindow")?;
+    }
+    if !precursor.selected_ions.is_empty() {
+        let count = precursor.selected_ions.len().to_string();
+        start(writer, "selectedIonList", &[("count", &count)])?;
+        for selected_ion in &precursor.selected_ions {
+            start(writer, "selectedIon", &[])?;
+            write_cv_params(writer, selected_ion)?;
+            end(writer, "selectedIon")?;
+        }
+        end(writer, "selectedIonList")?;
+    }
+    // <activation> is required by the schema even when empty
+    start(writer, "activation", &[])?;
+    write_cv_params(writer, &precursor.activation.cv_params)?;
+    end(writer, "activation")?;
+    end(writer, "precursor")
+}
+
+/// Writes a chromatogram <product>, rebuilding its isolation window params in m/z
+fn write_product<W: Write>(writer: &mut Writer<W>, product: &Product) -> Result<()> {
+    let window = &product.isolation_window;
+    start(writer, "product", &[])?;
+    start(writer, "isolationWindow", &[])?;
+    for (term, name, value) in [
+        (
+            accession::ISOLATION_WINDOW_TARGET_MZ,
+            "isolation window target m/z",
+            window.target_mz,
+        ),
+        (
+            accession::ISOLATION_WINDOW_LOWER_OFFSET,
+            "isolation window lower offset",
+            window.lower_offset,
+        ),
+        (
+            accession::ISOLATION_WINDOW_UPPER_OFFSET,
+            "isolation window upper offset",
+            window.upper_offset,
+        ),
+    ] {
+        if let Some(value) = value {
+            let mut param = cv_param(term, name, Some(&value.to_string()));
+            param.unit_cv_ref = Some("MS".into());
+            param.unit_accession = Some(accession::MZ.into());
+            param.unit_name = Some("m/z".into());
+            write_cv_param(writer, &param)?;
+        }
+    }
+    end(writer, "isolationWindow")?;
+    end(writer, "product")
+}
+
+fn write_binary_data_arrays<W: Write>(
+    writer: &mut Writer<W>,
+    arrays: &[BinaryDataArray],
+) -> Result<()> {
+    let count = arrays.len().to_string();
+    start(writer, "binaryDataArrayList", &[("count", &count)])?;
+
+    for array in arrays {
+        // Kept raw text and still-encoded (lazy) data go out verbatim with their own params
+        if let Some(raw_binary) = array.raw_binary.as_ref().or(array.encoded_data.as_ref()) {
+            let encoded_length = array.encoded_length.to_string();
+            start(
+                writer,
+                "binaryDataArray",
+                &[("encodedLength", &encoded_length)],
+            )?;
+            write_cv_params(writer, &array.cv_params)?;
+            start(writer, "binary", &[])?;
+            writer.write_event(Event::Text(BytesText::new(raw_binary)))?;
+            end(writer, "binary")?;
+            end(writer, "binaryDataArray")?;
+            continue;
+        }
+        let data = array.decoded_data.as_deref().unwrap_or_default();
+        let encoded = encode_binary_data(data);
+        let encoded_length = encoded.len().to_string();
+        start(
+            writer,
+            "binaryDataArray",
+            &[("encodedLength", &encoded_length)],
+        )?;
+        write_cv_param(
+            writer,
+            &cv_param(accession::FLOAT_32_BIT, "32-bit float", None),
+        )?;
+        write_cv_param(
+            writer,
+            &cv_param(accession::NO_COMPRESSION, "no compression", None),
+        )?;
+        for param in &array.cv_params {
+            if !ENCODING_ACCESSIONS.contains(&&*param.accession) {
+                write_cv_param(writer, param)?;
+            }
+        }
+        start(writer, "binary", &[])?;
+        writer.write_event(Event::Text(BytesText::new(&encoded)))?;
+        end(writer, "binary")?;
+        end(writer, "binaryDataArray")?;
+    }
+
+    end(writer, "binaryDataArrayList")
+}
+
+fn write_file_description<W: Write>(
+    writer: &mut Writer<W>,
+    file_description: Option<&FileDescription>,
+) -> Result<()> {
+    start(writer, "fileDescription", &[])?;
+    let Some(file_description) = file_description else {
+        empty(writer, "fileContent", &[])?;
+        return end(writer, "fileDescription");
+    };
+
+    start(writer, "fileContent", &[])?;
+    write_cv_params(writer, &file_description.file_content)?;
+    end(writer, "fileContent")?;
+
+    if !file_description.source_files.is_empty() {
+        let count = file_description.source_files.len().to_string();
+        start(writer, "sourceFileList", &[("count", &count)])?;
+        for source_file in &file_description.source_files {
+            start(
+                writer,
+                "sourceFile",
+                &[
+                    ("id", &source_file.id),
+                    ("name", &source_file.name),
+                    ("location", &source_file.location),
+                ],
+            )?;
+            write_cv_params(writer, &source_file.cv_params)?;
+            end(writer, "sourceFile")?;
+        }
+        end(writer, "sourceFileList")?;
+    }
+
+    end(writer, "fileDescription")
+}
+
+fn write_cv_params<W: Write>(writer: &mut Writer<W>, params: &[CvParam]) -> Result<()> {
+    for param in params {
+        write_cv_param(writer, param)?;
+    }
+    Ok(())
+}
+
+fn write_cv_param<W: Write>(writer: &mut Writer<W>, param: &CvParam) -> Result<()> {
+    let mut attributes = vec![
+        ("cvRef", &*param.cv_ref),
+        ("accession", &*param.accession),
+        ("name", &*param.name),
+        ("value", param.value.as_deref().unwrap_or("")),
+    ];
+    if let Some(unit_cv_ref) = &param.unit_cv_ref {
+        attributes.push(("unitCvRef", unit_cv_ref));
+    }
+    if let Some(unit_accession) = &param.unit_accession {
+        attributes.push(("unitAccession", unit_accession));
+    }
+    if let Some(unit_name) = &param.unit_name {
+        attributes.push(("unitName", unit_name));
+    }
+    empty(writer, "cvParam", &attributes)
+}
+
+fn write_user_params<W: Write>(writer: &mut Writer<W>, params: &[UserParam]) -> Result<()> {
+    for param in params {
+        let mut attributes = vec![("name", param.name.as_str())];
+        if let Some(value) = &param.value {
+            attributes.push(("value", value));
+        }
+        if let Some(type_) = &param.type_ {
+            attributes.push(("type", type_));
+        }
+        if let Some(unit_cv_ref) = &param.unit_cv_ref {
+            attributes.push(("unitCvRef", unit_cv_ref));
+        }
+        if let Some(unit_accession) = &param.unit_accession {
+            attributes.push(("unitAccession", unit_accession));
+        }
+        if let Some(unit_name) = &param.unit_name {
+            attributes.push(("unitName", unit_name));
+        }
+        empty(writer, "userParam", &attributes)?;
+    }
+    Ok(())
+}
+
+fn cv_param(accession: &str, name: &str, value: Option<&str>) -> CvParam {
+    CvParam {
+        cv_ref: "MS".into(),
+        accession: accession.into(),
+        name: name.into(),
+        value: value.map(str::to_string),
+        unit_name: None,
+        unit_accession: None,
+        unit_cv_ref: None,
+    }
+}
+
+pub(crate) fn start<W: Write>(
+    writer: &mut Writer<W>,
+    name: &str,
+    attributes: &[(&str, &str)],
+) -> Result<()> {
+    let element = BytesStart::new(name).with_attributes(attributes.iter().copied());
+    writer.write_event(Event::Start(element))?;
+    Ok(())
+}
+
+fn empty<W: Write>(writer: &mut Writer<W>, name: &str, attributes: &[(&str, &str)]) -> Result<()> {
+    let element = BytesStart::new(name).with_attributes(attributes.iter().copied());
+    writer.write_event(Event::Empty(element))?;
+    Ok(())
+}
+
+pub(crate) fn end<W: Write>(writer: &mut Writer<W>, name: &str) -> Result<()> {
+    writer.write_event(Event::End(BytesEnd::new(name)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::{DecodeMode, ParseOptions};
+    use crate::test_fixtures::{
+        binary_array, f64_base64, mrm_chromatogram, mzml, mzml_with_spectra, spectrum, zlib,
+    };
+
+    fn two_spectra() -> Run {
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 5.0), (101.0, 6.0), (102.0, 7.0)], ""),
+            spectrum(1, 2, &[(50.0, 1.0)], ""),
+        ]);
+        crate::parse_mzml(&xml).unwrap()
+    }
+
+    /// Writes a run as mzML and parses the output back
+    fn round_trip(run: &Run) -> Run {
+        let mut out = Vec::new();
+        write_mzml(run, &mut out).unwrap();
+        crate::parse_mzml(std::str::from_utf8(&out).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn mzml_round_trip_keeps_spectra() {
+        let run = two_spectra();
+
+        let back = round_trip(&run);
+        assert_eq!(back.spectra.len(), 2);
+        for (back, original) in back.spectra.iter().zip(&run.spectra) {
+            assert_eq!(back.id, original.id);
+            assert_eq!(back.default_array_length, original.default_array_length);
+            assert_eq!(back.mz_array(), original.mz_array());
+            assert_eq!(back.intensity_array(), original.intensity_array());
+        }
+    }
+
+    #[test]
+    fn csv_has_one_row_per_peak() {
+        let run = two_spectra();
+        let mut out = Vec::new();
+        write_spectra_csv(&run, &mut out).unwrap();
+
+        let csv = String::from_utf8(out).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("spectrum_id,mz,intensity"));
+        let peaks: usize = run.spectra.iter().map(|s| s.peak_count()).sum();
+        assert_eq!(lines.count(), peaks);
+        assert!(csv.contains("scan=2,50,1\n"));
+    }
+
+    #[test]
+    fn never_decoded_arrays_are_written() {
+        let xml = mzml_with_spectra(&[spectrum(0, 1, &[(100.0, 5.0), (101.0, 6.0)], "")]);
+        let options = ParseOptions::builder()
+            .decode_mode(DecodeMode::Lazy)
+            .build();
+        let lazy = crate::parse_mzml_with_options(&xml, &options).unwrap();
+        assert!(lazy.spectra[0].binary_data_arrays[0].decoded_data.is_none());
+
+        let back = round_trip(&lazy);
+        let spectrum = &back.spectra[0];
+        assert_eq!(spectrum.mz_array(), Some(&[100.0, 101.0][..]));
+        assert_eq!(spectrum.intensity_array(), Some(&[5.0, 6.0][..]));
+    }
+
+    #[test]
+    fn mrm_transition_round_trips() {
+        let run = crate::parse_mzml(&mzml(&mrm_chromatogram())).unwrap();
+
+        let back = round_trip(&run);
+        let (original, written) = (&run.chromatograms[0], &back.chromatograms[0]);
+        assert_eq!(written.precursors, original.precursors);
+        assert_eq!(written.products, original.products);
+        assert_eq!(written.intensity_array(), original.intensity_array());
+    }
+
+    /// Returns the text of every <binary> element, in document order
+    fn binaries(xml: &str) -> Vec<&str> {
+        xml.split("<binary>")
+            .skip(1)
+            .map(|rest| &rest[..rest.find("</binary>").unwrap()])
+            .collect()
+    }
+
+    #[test]
+    fn kept_raw_binary_is_written_byte_for_byte() {
+        use base64::Engine;
+
+        let mz: Vec<u8> = [100.1f64, 200.2, 300.3]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let arrays = [
+            binary_array(
+                &[
+                    accession::MZ_ARRAY,
+                    accession::FLOAT_64_BIT,
+                    accession::ZLIB_COMPRESSION,
+                ],
+                &base64::engine::general_purpose::STANDARD.encode(zlib(&mz)),
+            ),
+            binary_array(
+                &[
+                    accession::INTENSITY_ARRAY,
+                    accession::FLOAT_64_BIT,
+                    accession::NO_COMPRESSION,
+                ],
+                &f64_base64(&[1.0, 2.0, 3.0]),
+            ),
+        ];
+        let xml = mzml_with_spectra(&[format!(
+            r#"<spectrum index="0" id="scan=1" defaultArrayLength="3"><binaryDataArrayList count="2">{}</binaryDataArrayList></spectrum>"#,
+            arrays.concat()
+        )]);
+        let write = |options: &ParseOptions| {
+            let run = crate::parse_mzml_with_options(&xml, options).unwrap();
+            let mut out = Vec::new();
+            write_mzml(&run, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let kept = write(&ParseOptions::builder().keep_raw_binary(true).build());
+        assert_eq!(binaries(&kept), binaries(&xml));
+        assert!(kept.contains(accession::ZLIB_COMPRESSION));
+        let back = crate::parse_mzml(&kept).unwrap();
+        assert_eq!(back.spectra[0].mz_array(), Some(&[100.1, 200.2, 300.3][..]));
+
+        // Without the flag the arrays are re-encoded as uncompressed 32-bit floats
+        let reencoded = write(&ParseOptions::default());
+        assert_ne!(binaries(&reencoded), binaries(&xml));
+    }
+
+    #[test]
+    fn scan_instrument_configuration_ref_is_read_but_not_written() {
+        let scan_list =
+            r#"<scanList count="1"><scan instrumentConfigurationRef="IC2"/></scanList>"#;
+        let xml = mzml_with_spectra(&[
+            spectrum(0, 1, &[(100.0, 1.0)], scan_list),
+            spectrum(1, 1, &[(100.0, 1.0)], ""),
+        ]);
+        let run = crate::parse_mzml(&xml).unwrap();
+        assert_eq!(run.spectra[0].instrument_configuration_ref(), Some("IC2"));
+        assert_eq!(run.spectra[1].instrument_configuration_ref(), None);
+
+        // The writer declares only its own "IC" configuration, so "IC2" would dangle
+        let mut out = Vec::new();
+        write_mzml(&run, &mut out).unwrap();
+        let written = String::from_utf8(out).unwrap();
+        assert!(!written.contains("IC2"));
+        let back = crate::parse_mzml(&written).unwrap();
+        assert_eq!(back.spectra[0].instrument_configuration_ref(), None);
+    }
+}